@@ -0,0 +1,358 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, TryLockError,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeZone};
+
+use crate::{
+    capabilities::Capabilities,
+    commands::{BlockSpare, ConnectionState, LedState, Signature, WriteOutcome},
+    config::WriteMode,
+    error::Result,
+    fs::{
+        ChainLink, DefragReport, FatView, FileInfo, FragmentationReport, FsCopy, FsStats,
+        FsckReport, RecoverableFile, WriteProgress,
+    },
+    transport::Transport,
+    BBPlayer, DefaultTransport,
+};
+
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+pub struct AsyncBBPlayer<B: Transport = DefaultTransport> {
+    inner: Arc<Mutex<BBPlayer<B>>>,
+}
+
+impl<B: Transport> Clone for AsyncBBPlayer<B> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<B: Transport + Send + 'static> AsyncBBPlayer<B> {
+    pub fn new(player: BBPlayer<B>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(player)),
+        }
+    }
+
+    pub fn into_inner(self) -> Option<BBPlayer<B>> {
+        Arc::try_unwrap(self.inner)
+            .ok()
+            .map(|m| m.into_inner().expect("BBPlayer mutex poisoned"))
+    }
+
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut BBPlayer<B>) -> Result<T> + Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        tokio::task::spawn_blocking(move || f(&mut inner.lock().expect("BBPlayer mutex poisoned")))
+            .await?
+    }
+
+    pub async fn ping_async(&self) -> Result<Duration> {
+        self.run(|player| player.ping()).await
+    }
+
+    pub async fn probe_capabilities_async(&self) -> Result<Capabilities> {
+        self.run(|player| player.probe_capabilities()).await
+    }
+
+    // a session that's in the middle of another operation counts as busy
+    // rather than queueing a heartbeat behind it
+    pub async fn connection_state_async(&self) -> Result<ConnectionState> {
+        let inner = Arc::clone(&self.inner);
+        tokio::task::spawn_blocking(move || match inner.try_lock() {
+            Ok(player) => player.connection_state(),
+            Err(TryLockError::WouldBlock) => ConnectionState::Busy,
+            Err(TryLockError::Poisoned(_)) => panic!("BBPlayer mutex poisoned"),
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn init_async(&self) -> Result<()> {
+        self.run(|player| player.Init()).await
+    }
+
+    pub async fn get_bbid_async(&self) -> Result<u32> {
+        self.run(|player| player.GetBBID()).await
+    }
+
+    pub async fn set_led_async<T: Into<LedState>>(&self, state: T) -> Result<()> {
+        let state = state.into();
+        self.run(move |player| player.SetLED(state)).await
+    }
+
+    pub async fn set_time_async<Tz>(&self, when: DateTime<Tz>) -> Result<()>
+    where
+        Tz: TimeZone + Send + 'static,
+        Tz::Offset: Send,
+    {
+        self.run(move |player| player.SetTime(when)).await
+    }
+
+    pub async fn firmware_version_async(&self) -> Result<String> {
+        self.run(|player| player.FirmwareVersion()).await
+    }
+
+    pub async fn sign_hash_async(&self, hash: [u8; 20]) -> Result<Signature> {
+        self.run(move |player| player.SignHash(hash)).await
+    }
+
+    pub async fn list_file_entries_async(&self) -> Result<Vec<FileInfo>> {
+        self.run(|player| player.ListFileEntries()).await
+    }
+
+    pub async fn view_fat_async(&self) -> Result<FatView> {
+        self.run(|player| player.ViewFAT()).await
+    }
+
+    pub async fn fragmentation_report_async(&self) -> Result<FragmentationReport> {
+        self.run(|player| player.FragmentationReport()).await
+    }
+
+    pub async fn stat_file_async(&self, filename: String) -> Result<Option<FileInfo>> {
+        self.run(move |player| player.StatFile(filename)).await
+    }
+
+    #[deprecated(note = "use list_file_entries_async")]
+    #[allow(deprecated)]
+    pub async fn list_files_async(&self) -> Result<Vec<(String, u32)>> {
+        self.run(|player| player.ListFiles()).await
+    }
+
+    pub async fn list_file_blocks_async(&self, filename: String) -> Result<Option<Vec<u16>>> {
+        self.run(move |player| player.ListFileBlocks(filename))
+            .await
+    }
+
+    pub async fn file_chain_async(&self, filename: String) -> Result<Option<Vec<ChainLink>>> {
+        self.run(move |player| player.FileChain(filename)).await
+    }
+
+    pub async fn dump_current_fs_async(&self) -> Result<Vec<u8>> {
+        self.run(|player| player.DumpCurrentFS()).await
+    }
+
+    pub async fn list_fs_copies_async(&self) -> Result<Vec<FsCopy>> {
+        self.run(|player| player.ListFSCopies()).await
+    }
+
+    pub async fn list_fs_generations_async(&self) -> Result<Vec<FsCopy>> {
+        self.run(|player| player.ListFSGenerations()).await
+    }
+
+    pub async fn rollback_fs_async(&self, block: u32) -> Result<()> {
+        self.run(move |player| player.RollbackFS(block)).await
+    }
+
+    pub async fn get_stats_async(&self) -> Result<FsStats> {
+        self.run(|player| player.GetStats()).await
+    }
+
+    pub async fn dump_spares_async(&self) -> Result<Vec<u8>> {
+        self.run(|player| player.DumpSpares()).await
+    }
+
+    pub async fn dump_nand_async(&self) -> Result<BlockSpare> {
+        // dropping the future stops the dump at the next block boundary
+        let cancel = Arc::new(AtomicBool::new(false));
+        let _guard = CancelOnDrop(Arc::clone(&cancel));
+        self.run(move |player| player.DumpNANDCancellable(&cancel))
+            .await
+    }
+
+    pub async fn read_block_async(&self, block_num: u32) -> Result<BlockSpare> {
+        self.run(move |player| player.ReadSingleBlock(block_num))
+            .await
+    }
+
+    pub async fn read_raw_block_async(&self, block_num: u32) -> Result<Vec<u8>> {
+        self.run(move |player| player.ReadRawBlock(block_num)).await
+    }
+
+    pub async fn write_raw_block_async(&self, block: Vec<u8>, block_num: u32) -> Result<()> {
+        self.run(move |player| player.WriteRawBlock(block, block_num))
+            .await
+    }
+
+    pub async fn write_block_async(
+        &self,
+        block: Vec<u8>,
+        spare: Vec<u8>,
+        block_num: u32,
+        verify: bool,
+    ) -> Result<()> {
+        self.run(move |player| player.WriteSingleBlock(block, spare, block_num, verify))
+            .await
+    }
+
+    pub async fn write_block_preserving_spare_async<F>(
+        &self,
+        block: Vec<u8>,
+        block_num: u32,
+        verify: bool,
+        update_spare: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&mut [u8]) + Send + 'static,
+    {
+        self.run(move |player| {
+            player.WriteSingleBlockPreservingSpare(block, block_num, verify, update_spare)
+        })
+        .await
+    }
+
+    pub async fn write_nand_async(&self, nand: Vec<u8>, spare: Vec<u8>) -> Result<()> {
+        self.run(move |player| player.WriteNAND(nand, spare)).await
+    }
+
+    pub async fn resume_write_nand_async<F>(
+        &self,
+        nand: Vec<u8>,
+        spare: Vec<u8>,
+        start: u32,
+        on_block: F,
+    ) -> Result<()>
+    where
+        F: FnMut(u32, WriteOutcome) + Send + 'static,
+    {
+        self.run(move |player| player.ResumeWriteNAND(nand, spare, start, on_block))
+            .await
+    }
+
+    pub async fn read_file_async(&self, filename: String) -> Result<Option<Vec<u8>>> {
+        self.run(move |player| player.ReadFile(filename)).await
+    }
+
+    pub async fn write_file_async(&self, data: Vec<u8>, filename: String) -> Result<()> {
+        self.run(move |player| player.WriteFile(data, filename))
+            .await
+    }
+
+    // the progress comes back alongside the result so a failed upload can be
+    // resumed with it
+    pub async fn resume_write_async(
+        &self,
+        filename: String,
+        data: Vec<u8>,
+        mut progress: WriteProgress,
+    ) -> (Result<()>, WriteProgress) {
+        let fallback = progress.clone();
+        match self
+            .run(move |player| Ok((player.ResumeWrite(filename, data, &mut progress), progress)))
+            .await
+        {
+            Ok(rv) => rv,
+            Err(e) => (Err(e), fallback),
+        }
+    }
+
+    pub async fn write_files_async(&self, files: Vec<(String, Vec<u8>)>) -> Result<()> {
+        self.run(move |player| player.WriteFiles(&files)).await
+    }
+
+    pub async fn read_files_async(&self, filenames: Vec<String>) -> Result<Vec<Option<Vec<u8>>>> {
+        self.run(move |player| player.ReadFiles(&filenames)).await
+    }
+
+    pub async fn list_matching_async(&self, pattern: String) -> Result<Vec<FileInfo>> {
+        self.run(move |player| player.ListMatching(pattern)).await
+    }
+
+    pub async fn read_matching_async(&self, pattern: String) -> Result<Vec<(String, Vec<u8>)>> {
+        self.run(move |player| player.ReadMatching(pattern)).await
+    }
+
+    pub async fn delete_matching_async(&self, pattern: String) -> Result<Vec<String>> {
+        self.run(move |player| player.DeleteMatching(pattern)).await
+    }
+
+    pub async fn write_file_with_async(
+        &self,
+        data: Vec<u8>,
+        filename: String,
+        mode: WriteMode,
+    ) -> Result<()> {
+        self.run(move |player| player.WriteFileWith(data, filename, mode))
+            .await
+    }
+
+    pub async fn delete_file_async(&self, filename: String) -> Result<()> {
+        self.run(move |player| player.DeleteFile(filename)).await
+    }
+
+    pub async fn read_file_range_async(
+        &self,
+        filename: String,
+        offset: u32,
+        len: u32,
+    ) -> Result<Option<Vec<u8>>> {
+        self.run(move |player| player.ReadFileRange(filename, offset, len))
+            .await
+    }
+
+    pub async fn rename_file_async(&self, from: String, to: String) -> Result<()> {
+        self.run(move |player| player.RenameFile(from, to)).await
+    }
+
+    pub async fn shred_file_async(&self, filename: String) -> Result<()> {
+        self.run(move |player| player.ShredFile(filename)).await
+    }
+
+    pub async fn wipe_free_space_async(&self, fill: u8) -> Result<u32> {
+        // dropping the future stops the wipe at the next block boundary
+        let cancel = Arc::new(AtomicBool::new(false));
+        let _guard = CancelOnDrop(Arc::clone(&cancel));
+        self.run(move |player| player.WipeFreeSpaceCancellable(fill, &cancel))
+            .await
+    }
+
+    pub async fn list_recoverable_async(&self) -> Result<Vec<RecoverableFile>> {
+        self.run(|player| player.ListRecoverable()).await
+    }
+
+    pub async fn undelete_file_async(&self, filename: String) -> Result<()> {
+        self.run(move |player| player.UndeleteFile(filename)).await
+    }
+
+    pub async fn copy_file_async(&self, from: String, to: String) -> Result<()> {
+        self.run(move |player| player.CopyFile(from, to)).await
+    }
+
+    pub async fn check_fs_async(&self, repair: bool) -> Result<FsckReport> {
+        self.run(move |player| player.CheckFS(repair)).await
+    }
+
+    pub async fn format_fs_async(&self, scan_spares: bool) -> Result<()> {
+        self.run(move |player| player.FormatFS(scan_spares)).await
+    }
+
+    pub async fn defragment_async(&self) -> Result<DefragReport> {
+        self.run(|player| player.Defragment()).await
+    }
+
+    pub async fn recover_async(&self, reset_device: bool) -> Result<()> {
+        self.run(move |player| player.Recover(reset_device)).await
+    }
+
+    pub async fn close_async(&self) -> Result<()> {
+        self.run(|player| player.Close()).await
+    }
+}
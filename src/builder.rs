@@ -0,0 +1,193 @@
+use std::{
+    cell::{Cell, RefCell},
+    sync::Arc,
+    time::Duration,
+};
+
+#[cfg(all(feature = "nusb", not(feature = "rusb")))]
+use nusb::DeviceInfo;
+#[cfg(feature = "rusb")]
+use rusb::{Device, UsbContext};
+
+#[cfg(all(feature = "nusb", not(feature = "rusb")))]
+use crate::nusb::NusbTransport;
+#[cfg(feature = "rusb")]
+use crate::usb::UsbTransport;
+use crate::{
+    config::{
+        FsRefreshPolicy, LedFeedback, ReadyPolicy, RetryPolicy, SessionPolicy, SpareWriteMode,
+        TimeoutConfig,
+    },
+    error::{LibBBError, Result},
+    events::RetryHookHandle,
+    metrics::{MetricsSink, SinkHandle},
+    stats::TransferStats,
+    transport::Transport,
+    BBPlayer,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct BBPlayerBuilder {
+    timeouts: TimeoutConfig,
+    ready_policy: ReadyPolicy,
+    retry_policy: RetryPolicy,
+    spare_write_mode: SpareWriteMode,
+    session_policy: SessionPolicy,
+    fs_refresh_policy: FsRefreshPolicy,
+    allow_system_blocks: bool,
+    atomic_writes: bool,
+    led_feedback: Option<LedFeedback>,
+    on_retry: Option<RetryHookHandle>,
+    auto_init: bool,
+    // kernel drivers are detached unless this is set, as open always did
+    keep_kernel_driver: bool,
+    metrics: Option<SinkHandle>,
+}
+
+impl BBPlayerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts = TimeoutConfig::uniform(timeout);
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: TimeoutConfig) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn ready_policy(mut self, ready_policy: ReadyPolicy) -> Self {
+        self.ready_policy = ready_policy;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = RetryPolicy {
+            attempts: retry_policy.attempts.max(1),
+            ..retry_policy
+        };
+        self
+    }
+
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.retry_policy.attempts = attempts.max(1);
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.retry_policy.backoff = backoff;
+        self
+    }
+
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.retry_policy.verify = verify;
+        self
+    }
+
+    pub fn spare_write_mode(mut self, spare_write_mode: SpareWriteMode) -> Self {
+        self.spare_write_mode = spare_write_mode;
+        self
+    }
+
+    pub fn session_policy(mut self, session_policy: SessionPolicy) -> Self {
+        self.session_policy = session_policy;
+        self
+    }
+
+    pub fn fs_refresh_policy(mut self, fs_refresh_policy: FsRefreshPolicy) -> Self {
+        self.fs_refresh_policy = fs_refresh_policy;
+        self
+    }
+
+    pub fn allow_system_blocks(mut self, allow: bool) -> Self {
+        self.allow_system_blocks = allow;
+        self
+    }
+
+    pub fn atomic_writes(mut self, atomic: bool) -> Self {
+        self.atomic_writes = atomic;
+        self
+    }
+
+    pub fn led_feedback(mut self, led_feedback: LedFeedback) -> Self {
+        self.led_feedback = Some(led_feedback);
+        self
+    }
+
+    pub fn on_retry<F: Fn(u32, u32, &LibBBError) -> bool + Send + Sync + 'static>(
+        mut self,
+        hook: F,
+    ) -> Self {
+        self.on_retry = Some(RetryHookHandle(Arc::new(hook)));
+        self
+    }
+
+    pub fn auto_init(mut self, auto_init: bool) -> Self {
+        self.auto_init = auto_init;
+        self
+    }
+
+    pub fn force_detach(mut self, force_detach: bool) -> Self {
+        self.keep_kernel_driver = !force_detach;
+        self
+    }
+
+    pub fn metrics_sink<S: MetricsSink + Send + Sync + 'static>(mut self, sink: S) -> Self {
+        self.metrics = Some(SinkHandle(Arc::new(sink)));
+        self
+    }
+
+    #[cfg(feature = "rusb")]
+    pub fn open<C: UsbContext>(&self, device: &Device<C>) -> Result<BBPlayer<UsbTransport<C>>> {
+        let mut player = self.build(UsbTransport::open_with(device, !self.keep_kernel_driver)?);
+        if self.auto_init {
+            player.Init()?;
+        }
+        Ok(player)
+    }
+
+    #[cfg(all(feature = "nusb", not(feature = "rusb")))]
+    pub fn open(&self, device: &DeviceInfo) -> Result<BBPlayer<NusbTransport>> {
+        let mut player = self.build(NusbTransport::open(device)?);
+        if self.auto_init {
+            player.Init()?;
+        }
+        Ok(player)
+    }
+
+    pub fn build<B: Transport>(&self, transport: B) -> BBPlayer<B> {
+        BBPlayer {
+            transport,
+            current_fs_index: 0,
+            current_fs_block: None,
+            current_fs_spare: vec![],
+            is_initialised: false,
+            ready_pending: Cell::new(false),
+            timeouts: self.timeouts,
+            write_timeout: Cell::new(self.timeouts.block_write),
+            ready_policy: self.ready_policy,
+            stats: Cell::new(TransferStats::default()),
+            subscribers: RefCell::new(vec![]),
+            retry_policy: self.retry_policy,
+            metrics: self.metrics.clone(),
+            last_ret: Cell::new(None),
+            metered_arg: Cell::new(None),
+            capabilities: None,
+            num_blocks: Cell::new(None),
+            spare_write_mode: self.spare_write_mode,
+            session_policy: self.session_policy,
+            fs_refresh_policy: self.fs_refresh_policy,
+            allow_system_blocks: self.allow_system_blocks,
+            atomic_writes: self.atomic_writes,
+            dry_run: false,
+            plan: RefCell::new(vec![]),
+            led_feedback: self.led_feedback,
+            on_retry: self.on_retry.clone(),
+            #[cfg(feature = "rusb")]
+            keep_kernel_driver: self.keep_kernel_driver,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use log::debug;
+
+use crate::{
+    commands::Command,
+    error::{LibBBError, Result},
+    transport::Transport,
+    BBPlayer,
+};
+
+// which of the optional commands the console's firmware understands. the write
+// commands can't be tried without writing to the NAND, so they're assumed to
+// come with their read counterparts, which every SK seen so far implements in
+// pairs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub supports_raw_read: bool,
+    pub supports_raw_write: bool,
+    pub supports_spare_read: bool,
+    pub supports_spare_write: bool,
+    pub supports_sign_hash: bool,
+}
+
+impl Capabilities {
+    pub fn supports(&self, command: Command) -> bool {
+        match command {
+            Command::ReadBlock => self.supports_raw_read,
+            Command::WriteBlock => self.supports_raw_write,
+            Command::ReadBlockAndSpare => self.supports_spare_read,
+            Command::WriteBlockAndSpare => self.supports_spare_write,
+            Command::SignHash => self.supports_sign_hash,
+            _ => true,
+        }
+    }
+}
+
+impl<B: Transport> BBPlayer<B> {
+    // tries each optional command once, without retries; anything the console
+    // refuses or doesn't answer sensibly is recorded as unsupported. until this
+    // has been run, every command is assumed to be supported
+    pub fn probe_capabilities(&mut self) -> Result<Capabilities> {
+        self.capabilities = None;
+
+        let supports_raw_read = self.probe_block_read(Command::ReadBlock)?;
+        let supports_spare_read = self.probe_block_read(Command::ReadBlockAndSpare)?;
+        let supports_sign_hash = self.probe_sign_hash()?;
+
+        let capabilities = Capabilities {
+            supports_raw_read,
+            supports_raw_write: supports_raw_read,
+            supports_spare_read,
+            supports_spare_write: supports_spare_read,
+            supports_sign_hash,
+        };
+        debug!("{capabilities:?}");
+        self.capabilities = Some(capabilities);
+        Ok(capabilities)
+    }
+
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities
+    }
+
+    pub(crate) fn require(&self, command: Command) -> Result<()> {
+        match self.capabilities {
+            Some(capabilities) if !capabilities.supports(command) => {
+                Err(LibBBError::Unsupported(command))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    cell::RefCell,
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+use crate::{
+    error::{LibBBError, Result},
+    transport::Transport,
+};
+
+// records every bulk transfer as one JSON object per line:
+// {"us":<microseconds since capture start>,"dir":"out"|"in","len":<bytes>,"data":"<hex>"}
+// failed transfers carry "error":"<message>" in place of "len" and "data"
+#[derive(Debug)]
+pub struct CaptureTransport<B: Transport, W: Write = BufWriter<File>> {
+    inner: B,
+    writer: RefCell<W>,
+    start: Instant,
+}
+
+impl<B: Transport> CaptureTransport<B> {
+    pub fn to_file<P: AsRef<Path>>(inner: B, path: P) -> Result<Self> {
+        Ok(Self::new(inner, BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<B: Transport, W: Write> CaptureTransport<B, W> {
+    pub fn new(inner: B, writer: W) -> Self {
+        Self {
+            inner,
+            writer: RefCell::new(writer),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn into_inner(self) -> (B, W) {
+        (self.inner, self.writer.into_inner())
+    }
+
+    fn record(&self, dir: &str, data: &[u8]) {
+        let mut line = self.line_start(dir);
+        let _ = write!(line, "\"len\":{},\"data\":\"", data.len());
+        for b in data {
+            let _ = write!(line, "{b:02x}");
+        }
+        line.push_str("\"}");
+        self.write_line(&line);
+    }
+
+    fn record_error(&self, dir: &str, error: &LibBBError) {
+        let mut line = self.line_start(dir);
+        let _ = write!(line, "\"error\":\"{}\"}}", escape(&error.to_string()));
+        self.write_line(&line);
+    }
+
+    fn line_start(&self, dir: &str) -> String {
+        format!(
+            "{{\"us\":{},\"dir\":\"{dir}\",",
+            self.start.elapsed().as_micros()
+        )
+    }
+
+    fn write_line(&self, line: &str) {
+        if let Err(e) = writeln!(self.writer.borrow_mut(), "{line}") {
+            warn!("failed to write USB capture: {e}");
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl<B: Transport, W: Write> Transport for CaptureTransport<B, W> {
+    fn send(&self, data: &[u8], timeout: Duration) -> Result<usize> {
+        let rv = self.inner.send(data, timeout);
+        match &rv {
+            Ok(n) => self.record("out", &data[..*n]),
+            Err(e) => self.record_error("out", e),
+        }
+        rv
+    }
+
+    fn receive(&self, length: usize, timeout: Duration) -> Result<Vec<u8>> {
+        let rv = self.inner.receive(length, timeout);
+        match &rv {
+            Ok(buf) => self.record("in", buf),
+            Err(e) => self.record_error("in", e),
+        }
+        rv
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.inner.reset()
+    }
+
+    fn clear_halt(&mut self) -> Result<()> {
+        self.inner.clear_halt()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let rv = self.inner.close();
+        if let Err(e) = self.writer.get_mut().flush() {
+            warn!("failed to flush USB capture: {e}");
+        }
+        rv
+    }
+}
@@ -1,15 +1,32 @@
-use std::ffi::CString;
+use std::{
+    ffi::CString,
+    sync::atomic::{AtomicBool, Ordering},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+#[cfg(feature = "mmap")]
+use std::{fs::OpenOptions, path::Path};
 
 use crate::{
-    constants::{BLOCK_CHUNK_SIZE, BLOCK_SIZE, SPARE_SIZE},
+    config::SpareWriteMode,
+    constants::{
+        BLOCK_CHUNK_SIZE, BLOCK_SIZE, CMD_CONTENT_ID_OFFSET, FS_START_BLOCK, HASH_SIZE,
+        MAX_BLOCK_WRITE_TIMEOUT, SA_CMD_BLOCK, SIGNATURE_SIZE, SKSA_BLOCKS, SPARE_SIZE,
+    },
     error::{LibBBError, Result},
-    num_from_arr, BBPlayer,
+    events::Event,
+    filename::FatFilename,
+    num_from_arr,
+    replies::{ChecksumReply, NumBlocksReply, SeqNoReply, StatusReply},
+    transport::Transport,
+    BBPlayer,
 };
 
 use indicatif::ProgressIterator;
+use log::{debug, warn};
 
 #[repr(u32)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Command {
     WriteBlock = 0x06,
     ReadBlock = 0x07,
@@ -31,54 +48,208 @@ pub enum Command {
 
 pub type BlockSpare = (Vec<u8>, Vec<u8>);
 
+// an ECDSA signature over sect233r1 from the console's private key; r and s are
+// 30 bytes each, stored as big-endian u32 words (so zero-padded to 32 bytes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature(pub [u8; SIGNATURE_SIZE]);
+
+impl Signature {
+    pub fn r(&self) -> &[u8] {
+        &self.0[..SIGNATURE_SIZE / 2]
+    }
+
+    pub fn s(&self) -> &[u8] {
+        &self.0[SIGNATURE_SIZE / 2..]
+    }
+}
+
+// the values the player firmware accepts for SetLED; anything else it treats
+// as raw pattern bits, which Pattern passes through untouched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedState {
+    Off,
+    Power,
+    ErrorBlink,
+    Pattern(u32),
+}
+
+impl From<LedState> for u32 {
+    fn from(value: LedState) -> Self {
+        match value {
+            LedState::Off => 0,
+            LedState::Power => 2,
+            LedState::ErrorBlink => 4,
+            LedState::Pattern(bits) => bits,
+        }
+    }
+}
+
+impl From<u32> for LedState {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => Self::Off,
+            2 => Self::Power,
+            4 => Self::ErrorBlink,
+            bits => Self::Pattern(bits),
+        }
+    }
+}
+
+// a block write a dry run would have made (see BBPlayer::take_plan)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlannedWrite {
+    pub block: u32,
+    pub with_spare: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    Written,
+    SkippedBad,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    Busy,
+    Gone,
+}
+
+// retry transient failures; stalls, disconnects and errors reported by the
+// console won't go away by asking again (except a failed block write, see
+// LibBBError::CheckBlockWrite)
 macro_rules! try_continue {
-    ($e:expr) => {
+    ($self:ident, $block:expr, $attempt:expr, $e:expr) => {
         match $e {
             Ok(x) => x,
-            Err(e) => {
-                eprintln!("{e}");
+            Err(e) if e.is_transient() => {
+                warn!("{e}");
+                $self.emit(Event::Warning(e.to_string()));
+                if !$self.should_retry($block, $attempt, &e) {
+                    return Err(e);
+                }
                 continue;
             }
+            Err(e) => return Err(e),
         }
     };
 }
 
-impl BBPlayer {
-    fn command_ret(buf: &[u8]) -> i32 {
-        num_from_arr(&buf[4..8])
+impl<B: Transport> BBPlayer<B> {
+    fn prepare_attempt(&self, attempt: u32) -> Result<()> {
+        if attempt > 0 {
+            self.update_stats(|s| s.retries += 1);
+            let policy = self.retry_policy;
+            debug!("retrying (attempt {} of {})", attempt + 1, policy.attempts);
+            self.emit(Event::Retry {
+                attempt: attempt + 1,
+                attempts: policy.attempts,
+            });
+            if !policy.backoff.is_zero() {
+                sleep(policy.backoff);
+            }
+            self.resync()?;
+        }
+        Ok(())
+    }
+
+    // gives the on_retry hook, if there is one, the chance to stop retrying
+    fn should_retry(&self, block_num: u32, attempt: u32, error: &LibBBError) -> bool {
+        match &self.on_retry {
+            Some(hook) => (hook.0)(block_num, attempt + 1, error),
+            None => true,
+        }
     }
 
     pub(super) fn read_block_spare(&self, block_num: u32) -> Result<BlockSpare> {
-        // attempts
-        for _ in 0..5 {
-            self.request_block_read(Command::ReadBlockAndSpare, block_num)?;
-            let block = try_continue!(self.get_block());
-            let spare = try_continue!(self.get_spare());
-            return Ok((block, spare));
-        }
-        Err(LibBBError::ReadBlock(block_num))
+        self.metered(Command::ReadBlockAndSpare, || {
+            for attempt in 0..self.retry_policy.attempts {
+                self.prepare_attempt(attempt)?;
+                let start = Instant::now();
+                try_continue!(
+                    self,
+                    block_num,
+                    attempt,
+                    self.request_block_read(Command::ReadBlockAndSpare, block_num)
+                );
+                let block = try_continue!(self, block_num, attempt, self.get_block());
+                let spare = try_continue!(self, block_num, attempt, self.get_spare());
+                self.update_stats(|s| {
+                    s.blocks_read += 1;
+                    s.block_read_time += start.elapsed();
+                });
+                return Ok((block, spare));
+            }
+            Err(LibBBError::ReadBlock(block_num, self.retry_policy.attempts))
+        })
+    }
+
+    // ReadBlock (0x07) sends just the page data, no spare
+    pub(super) fn read_raw_block(&self, block_num: u32) -> Result<Vec<u8>> {
+        self.metered(Command::ReadBlock, || {
+            for attempt in 0..self.retry_policy.attempts {
+                self.prepare_attempt(attempt)?;
+                try_continue!(
+                    self,
+                    block_num,
+                    attempt,
+                    self.request_block_read(Command::ReadBlock, block_num)
+                );
+                return Ok(try_continue!(self, block_num, attempt, self.get_block()));
+            }
+            Err(LibBBError::ReadBlock(block_num, self.retry_policy.attempts))
+        })
     }
 
     fn request_block_read(&self, command: Command, block_num: u32) -> Result<()> {
         self.send_command(command as u32, block_num)?;
-        let ret = Self::command_ret(&self.receive_reply(8)?);
-        if ret < 0 {
-            Err(LibBBError::Command(command, ret))
-        } else {
-            Ok(())
-        }
+        self.receive_typed::<StatusReply>(command)?
+            .check(|ret| LibBBError::Command(command, ret))?;
+        Ok(())
     }
 
     fn get_block(&self) -> Result<Vec<u8>> {
         let mut buf = Vec::with_capacity(BLOCK_SIZE);
         for _ in 0..(BLOCK_SIZE / BLOCK_CHUNK_SIZE) {
-            buf.extend(self.receive_reply(BLOCK_CHUNK_SIZE)?);
+            buf.extend(self.receive_reply_within(BLOCK_CHUNK_SIZE, self.timeouts.block_read)?);
+        }
+        if buf.len() != BLOCK_SIZE {
+            return Err(LibBBError::ShortBlock(BLOCK_SIZE, buf.len()));
         }
         Ok(buf)
     }
 
     fn get_spare(&self) -> Result<Vec<u8>> {
-        self.receive_reply(SPARE_SIZE)
+        self.receive_reply_within(SPARE_SIZE, self.timeouts.block_read)
+    }
+
+    // block 0 holds the SK, which is always there and never written here
+    pub(super) fn probe_block_read(&self, command: Command) -> Result<bool> {
+        let result = self.request_block_read(command, 0).and_then(|_| {
+            self.get_block()?;
+            if command == Command::ReadBlockAndSpare {
+                self.get_spare()?;
+            }
+            Ok(())
+        });
+        self.probe_result(command, result)
+    }
+
+    pub(super) fn probe_sign_hash(&self) -> Result<bool> {
+        let result = self.sign_hash([0; HASH_SIZE]).map(|_| ());
+        self.probe_result(Command::SignHash, result)
+    }
+
+    fn probe_result(&self, command: Command, result: Result<()>) -> Result<bool> {
+        match result {
+            Ok(()) => Ok(true),
+            Err(e) if e.is_disconnect() => Err(e),
+            Err(e) => {
+                debug!("{command:?} not supported: {e}");
+                self.resync()?;
+                Ok(false)
+            }
+        }
     }
 
     pub(super) fn write_block_spare(
@@ -86,35 +257,136 @@ impl BBPlayer {
         block: &[u8],
         spare: &[u8],
         block_num: u32,
-    ) -> Result<()> {
-        if spare[5] != 0xFF {
-            // block is marked bad
-            return Ok(());
-        }
+        verify: bool,
+    ) -> Result<WriteOutcome> {
+        self.metered(Command::WriteBlockAndSpare, || {
+            if spare[5] != 0xFF {
+                // block is marked bad
+                return Ok(WriteOutcome::SkippedBad);
+            }
+            if self.dry_run {
+                self.plan_write(block_num, true);
+                return Ok(WriteOutcome::Written);
+            }
 
-        // attempts
-        for _ in 0..5 {
-            try_continue!(self.request_block_write(Command::WriteBlockAndSpare, block_num));
-            try_continue!(self.send_block(block));
-            try_continue!(self.send_spare(spare));
-            try_continue!(self.check_block_write());
-            return Ok(());
+            for attempt in 0..self.retry_policy.attempts {
+                self.prepare_attempt(attempt)?;
+                try_continue!(
+                    self,
+                    block_num,
+                    attempt,
+                    self.request_block_write(Command::WriteBlockAndSpare, block_num)
+                );
+                try_continue!(self, block_num, attempt, self.send_block(block));
+                try_continue!(self, block_num, attempt, self.send_spare(spare));
+                try_continue!(self, block_num, attempt, self.check_block_write());
+                if verify || self.retry_policy.verify {
+                    // a block that didn't take is worth writing again
+                    match self.verify_block(block, block_num) {
+                        Err(e @ LibBBError::VerifyFailed(_))
+                            if attempt + 1 < self.retry_policy.attempts =>
+                        {
+                            warn!("{e}");
+                            self.emit(Event::Warning(e.to_string()));
+                            if !self.should_retry(block_num, attempt, &e) {
+                                return Err(e);
+                            }
+                            continue;
+                        }
+                        result => result?,
+                    }
+                }
+                return Ok(WriteOutcome::Written);
+            }
+            Err(LibBBError::WriteBlock(
+                block_num,
+                self.retry_policy.attempts,
+            ))
+        })
+    }
+
+    // only the page data is compared; the console fills in the ECC bytes of
+    // the spare itself, so they won't match what was sent
+    fn verify_block(&self, block: &[u8], block_num: u32) -> Result<()> {
+        if self.read_raw_block(block_num)? != block {
+            return Err(LibBBError::VerifyFailed(block_num));
         }
-        Err(LibBBError::WriteBlock(block_num))
+        Ok(())
+    }
+
+    // WriteBlock (0x06) leaves the spare to the console; there's no bad block
+    // check here since there's no spare to check
+    pub(super) fn write_raw_block(&self, block: &[u8], block_num: u32) -> Result<()> {
+        self.metered(Command::WriteBlock, || {
+            if block.len() != BLOCK_SIZE {
+                return Err(LibBBError::BadBlockLength(BLOCK_SIZE, block.len()));
+            }
+            if self.dry_run {
+                self.plan_write(block_num, false);
+                return Ok(());
+            }
+
+            for attempt in 0..self.retry_policy.attempts {
+                self.prepare_attempt(attempt)?;
+                try_continue!(
+                    self,
+                    block_num,
+                    attempt,
+                    self.request_block_write(Command::WriteBlock, block_num)
+                );
+                try_continue!(self, block_num, attempt, self.send_block(block));
+                try_continue!(self, block_num, attempt, self.check_block_write());
+                return Ok(());
+            }
+            Err(LibBBError::WriteBlock(
+                block_num,
+                self.retry_policy.attempts,
+            ))
+        })
+    }
+
+    fn plan_write(&self, block: u32, with_spare: bool) {
+        debug!("dry run: would write block {block}");
+        self.plan
+            .borrow_mut()
+            .push(PlannedWrite { block, with_spare });
     }
 
     fn request_block_write(&self, command: Command, block_num: u32) -> Result<()> {
         self.send_command(command as u32, block_num)?;
-        self.wait_ready()
+        self.wait_ready_slow()
     }
 
+    // a write that has to erase first can take well over the usual timeout; the
+    // block's already been sent, so retrying it would only desync the console.
+    // keep waiting with a longer timeout instead, and remember it for next time
     fn check_block_write(&self) -> Result<()> {
-        let ret = Self::command_ret(&self.receive_reply(8)?);
-        if ret < 0 {
-            Err(LibBBError::CheckBlockWrite(ret))
-        } else {
-            Ok(())
-        }
+        let mut timeout = self.write_timeout.get();
+        let reply = loop {
+            let start = Instant::now();
+            match self.receive_typed_within::<StatusReply>(Command::WriteBlockAndSpare, timeout) {
+                Ok(reply) => {
+                    if start.elapsed() > timeout / 2 {
+                        self.extend_write_timeout(timeout);
+                    }
+                    break reply;
+                }
+                Err(e) if e.is_timeout() && timeout < MAX_BLOCK_WRITE_TIMEOUT => {
+                    timeout = self.extend_write_timeout(timeout);
+                    debug!("slow block write, waiting up to {timeout:?}");
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        reply.check(LibBBError::CheckBlockWrite)?;
+        Ok(())
+    }
+
+    fn extend_write_timeout(&self, timeout: Duration) -> Duration {
+        let extended = (timeout * 2).min(MAX_BLOCK_WRITE_TIMEOUT);
+        self.write_timeout.set(extended);
+        extended
     }
 
     fn send_block(&self, data: &[u8]) -> Result<()> {
@@ -123,7 +395,10 @@ impl BBPlayer {
 
     fn send_spare(&self, data: &[u8]) -> Result<()> {
         self.wait_ready()?;
-        let data = [&data[..3], &[0xFF; SPARE_SIZE - 3]].concat();
+        let data = match self.spare_write_mode {
+            SpareWriteMode::Metadata => [&data[..3], &[0xFF; SPARE_SIZE - 3]].concat(),
+            SpareWriteMode::Full => data.to_vec(),
+        };
         match self.send_piecemeal_data(data) {
             Ok(_) => Ok(()),
             Err(e) => Err(e),
@@ -131,44 +406,72 @@ impl BBPlayer {
     }
 
     pub(super) fn init_fs(&self) -> Result<()> {
-        self.send_command(Command::InitFS as u32, 0x00)?;
-        let ret = Self::command_ret(&self.receive_reply(8)?);
-        if ret < 0 {
-            Err(LibBBError::InitFS(ret))
+        self.metered(Command::InitFS, || {
+            self.send_command(Command::InitFS as u32, 0x00)?;
+            self.receive_typed::<StatusReply>(Command::InitFS)?
+                .check(LibBBError::InitFS)?;
+            Ok(())
+        })
+    }
+
+    pub(super) fn get_num_blocks(&self) -> Result<u32> {
+        self.metered(Command::GetNumBlocks, || {
+            self.send_command(Command::GetNumBlocks as u32, 0x00)?;
+            let NumBlocksReply(size) = self.receive_typed(Command::GetNumBlocks)?;
+            self.num_blocks.set(Some(size));
+            Ok(size)
+        })
+    }
+
+    // the block count doesn't change while connected, so after the first time
+    // (normally during Init) this doesn't need to ask the console
+    pub(super) fn check_block_num(&self, block_num: u32) -> Result<()> {
+        let num_blocks = match self.num_blocks.get() {
+            Some(n) => n,
+            None => self.get_num_blocks()?,
+        };
+        if block_num >= num_blocks {
+            Err(LibBBError::BlockOutOfRange(block_num, num_blocks))
         } else {
             Ok(())
         }
     }
 
-    pub(super) fn get_num_blocks(&self) -> Result<u32> {
-        self.send_command(Command::GetNumBlocks as u32, 0x00)?;
-        let reply = self.receive_reply(8)?;
-        let size: u32 = num_from_arr(&reply[4..8]);
-        Ok(size)
+    // overwriting these by accident can leave the console unable to boot, so
+    // the single-block writes refuse them unless asked not to
+    pub(super) fn check_writable(&self, block_num: u32) -> Result<()> {
+        if !self.allow_system_blocks && !(SKSA_BLOCKS..FS_START_BLOCK).contains(&block_num) {
+            Err(LibBBError::SystemBlock(block_num))
+        } else {
+            Ok(())
+        }
     }
 
     pub(super) fn set_seqno(&self, arg: u32) -> Result<()> {
-        self.send_command(Command::SetSeqNo as u32, arg)?;
-        self.receive_reply(8)?;
-        Ok(())
+        self.metered(Command::SetSeqNo, || {
+            self.send_command(Command::SetSeqNo as u32, arg)?;
+            self.receive_typed::<StatusReply>(Command::SetSeqNo)?;
+            Ok(())
+        })
+    }
+
+    pub(super) fn get_seqno(&self) -> Result<u32> {
+        self.metered(Command::GetSeqNo, || {
+            self.send_command(Command::GetSeqNo as u32, 0x00)?;
+            let SeqNoReply(seqno) = self.receive_typed(Command::GetSeqNo)?;
+            Ok(seqno)
+        })
     }
 
     pub(super) fn file_checksum_cmp(&self, filename: &str, chksum: u32, size: u32) -> Result<bool> {
-        self.send_filename(filename)?;
-        self.send_params_and_receive_reply(chksum, size)
+        self.metered(Command::FileChksum, || {
+            self.send_filename(filename)?;
+            self.send_params_and_receive_reply(chksum, size)
+        })
     }
 
     fn send_filename(&self, filename: &str) -> Result<()> {
-        let split = filename.split('.').collect::<Vec<_>>();
-        let (name, ext) = if split.len() > 1 {
-            (split[0], split[1])
-        } else {
-            (split[0], "")
-        };
-
-        if name.len() > 8 || ext.len() > 3 {
-            return Err(LibBBError::FileNameTooLong(filename.to_string()));
-        }
+        FatFilename::new(filename)?;
 
         let send_buf = match CString::new(filename) {
             Ok(f) => f,
@@ -180,7 +483,7 @@ impl BBPlayer {
             send_buf.as_bytes_with_nul().len() as u32,
         )?;
 
-        self.wait_ready()?;
+        self.wait_ready_slow()?;
 
         self.send_piecemeal_data(
             [
@@ -197,62 +500,191 @@ impl BBPlayer {
     fn send_params_and_receive_reply(&self, chksum: u32, size: u32) -> Result<bool> {
         self.send_command(chksum, size)?;
         //self.wait_ready()?;
-        let reply = self.receive_reply(8)?;
-        Ok(num_from_arr::<i32, _>(&reply[4..8]) == 0)
+        let reply: ChecksumReply = self.receive_typed(Command::FileChksum)?;
+        Ok(reply.matches())
     }
 
-    pub(super) fn set_led(&self, ledval: u32) -> Result<()> {
-        self.send_command(Command::SetLED as u32, ledval)?;
-        self.receive_reply(8)?;
-        Ok(())
+    pub(super) fn set_led(&self, state: LedState) -> Result<()> {
+        self.metered(Command::SetLED, || {
+            self.send_command(Command::SetLED as u32, state.into())?;
+            self.receive_typed::<StatusReply>(Command::SetLED)?;
+            Ok(())
+        })
     }
 
     pub(super) fn set_time(&self, timedata: [u8; 8]) -> Result<()> {
-        let first_half = num_from_arr(*timedata.split_array_ref::<4>().0);
-        let second_half = &timedata[4..];
-        self.send_command(Command::SetTime as u32, first_half)?;
-        let ret = Self::command_ret(&self.receive_reply(8)?);
-        if ret < 0 {
-            Err(LibBBError::SetTime(ret))
-        } else {
+        self.metered(Command::SetTime, || {
+            let first_half = num_from_arr(*timedata.split_array_ref::<4>().0);
+            let second_half = &timedata[4..];
+            self.send_command(Command::SetTime as u32, first_half)?;
+            self.receive_typed::<StatusReply>(Command::SetTime)?
+                .check(LibBBError::SetTime)?;
             self.send_piecemeal_data(second_half)?;
             Ok(())
-        }
+        })
+    }
+
+    pub(super) fn sign_hash(&self, hash: [u8; HASH_SIZE]) -> Result<Signature> {
+        self.metered(Command::SignHash, || {
+            self.send_command(Command::SignHash as u32, HASH_SIZE as u32)?;
+            self.wait_ready()?;
+            self.send_piecemeal_data(hash)?;
+
+            self.receive_typed::<StatusReply>(Command::SignHash)?
+                .check(|ret| LibBBError::Command(Command::SignHash, ret))?;
+            self.receive_typed(Command::SignHash)
+        })
     }
 
     pub(super) fn get_bbid(&self) -> Result<u32> {
-        self.send_command(Command::GetBBID as u32, 0x00)?;
-        let reply = self.receive_reply(8)?;
-        let ret = Self::command_ret(&reply);
-        if ret < 0 {
-            Err(LibBBError::GetBBID(ret))
-        } else {
-            Ok(num_from_arr(&reply[4..8]))
-        }
+        self.metered(Command::GetBBID, || {
+            self.send_command(Command::GetBBID as u32, 0x00)?;
+            self.receive_typed::<StatusReply>(Command::GetBBID)?
+                .check(LibBBError::GetBBID)
+        })
     }
 
     pub(super) fn dump_nand_and_spare(&self) -> Result<BlockSpare> {
+        self.dump_nand_and_spare_cancellable(&AtomicBool::new(false))
+    }
+
+    pub(super) fn dump_nand_and_spare_cancellable(
+        &self,
+        cancel: &AtomicBool,
+    ) -> Result<BlockSpare> {
         let num_blocks = self.get_num_blocks()?;
         let mut nand = Vec::with_capacity(num_blocks as usize * BLOCK_SIZE);
         let mut spare = Vec::with_capacity(num_blocks as usize * SPARE_SIZE);
+        let bulk = self.start_bulk("DumpNAND", num_blocks);
         for block_num in (0..num_blocks).progress() {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(LibBBError::Cancelled(nand, spare));
+            }
             let (dumped_block, dumped_spare) = self.read_block_spare(block_num)?;
             nand.extend(dumped_block);
             spare.extend(dumped_spare);
+            bulk.advance();
         }
+        bulk.finish();
         Ok((nand, spare))
     }
 
+    // there's no spare-only read, so each block still comes over the wire; only
+    // the spares are kept, though, so this needs 16 bytes a block rather than 16K
+    pub(super) fn dump_spares(&self) -> Result<Vec<u8>> {
+        let num_blocks = self.get_num_blocks()?;
+        let mut spares = Vec::with_capacity(num_blocks as usize * SPARE_SIZE);
+        let bulk = self.start_bulk("DumpSpares", num_blocks);
+        for block_num in (0..num_blocks).progress() {
+            let (_, spare) = self.read_block_spare(block_num)?;
+            spares.extend(spare);
+            bulk.advance();
+        }
+        bulk.finish();
+        Ok(spares)
+    }
+
+    #[cfg(feature = "mmap")]
+    pub(super) fn dump_nand_to_mmap(&self, path: &Path) -> Result<memmap2::Mmap> {
+        let num_blocks = self.get_num_blocks()?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(num_blocks as u64 * BLOCK_SIZE as u64)?;
+
+        // SAFETY: the file was just created and sized by us, and nothing else
+        // should be modifying it while the dump is running
+        let mut map = unsafe { memmap2::MmapMut::map_mut(&file)? };
+        let bulk = self.start_bulk("DumpNAND", num_blocks);
+        for block_num in (0..num_blocks).progress() {
+            let (block, _) = self.read_block_spare(block_num)?;
+            let offset = block_num as usize * BLOCK_SIZE;
+            map[offset..offset + BLOCK_SIZE].copy_from_slice(&block);
+            bulk.advance();
+        }
+        bulk.finish();
+        map.flush()?;
+        Ok(map.make_read_only()?)
+    }
+
     pub(super) fn read_single_block(&self, block_num: u32) -> Result<BlockSpare> {
         self.read_block_spare(block_num)
     }
 
+    pub(super) fn firmware_version(&self) -> Result<String> {
+        // the SK carries no version of its own, so report SA1's content ID
+        // (BbContentMetaDataHead::id) as stored in its CMD
+        let (block, _) = self.read_block_spare(SA_CMD_BLOCK)?;
+        let content_id: u32 =
+            num_from_arr(&block[CMD_CONTENT_ID_OFFSET..CMD_CONTENT_ID_OFFSET + 4]);
+        Ok(content_id.to_string())
+    }
+
     pub(super) fn write_single_block(
         &self,
         block: &[u8],
         spare: &[u8],
         block_num: u32,
+        verify: bool,
     ) -> Result<()> {
-        self.write_block_spare(block, spare, block_num)
+        if block.len() != BLOCK_SIZE {
+            return Err(LibBBError::BadBlockLength(BLOCK_SIZE, block.len()));
+        }
+        if spare.len() != SPARE_SIZE {
+            return Err(LibBBError::BadSpareLength(SPARE_SIZE, spare.len()));
+        }
+        self.write_block_spare(block, spare, block_num, verify)?;
+        Ok(())
+    }
+
+    // starts from the spare already on the NAND, so the caller only has to
+    // change the bytes it cares about
+    pub(super) fn write_single_block_preserving_spare<F: FnOnce(&mut [u8])>(
+        &self,
+        block: &[u8],
+        block_num: u32,
+        verify: bool,
+        update_spare: F,
+    ) -> Result<()> {
+        let (_, mut spare) = self.read_block_spare(block_num)?;
+        update_spare(&mut spare);
+        self.write_single_block(block, &spare, block_num, verify)
+    }
+
+    pub(super) fn write_nand_and_spare<F: FnMut(u32, WriteOutcome)>(
+        &self,
+        nand: &[u8],
+        spare: &[u8],
+        start: u32,
+        mut on_block: F,
+    ) -> Result<()> {
+        let num_blocks = self.get_num_blocks()?;
+        if nand.len() != num_blocks as usize * BLOCK_SIZE
+            || spare.len() != num_blocks as usize * SPARE_SIZE
+        {
+            return Err(LibBBError::IncorrectNANDSize(
+                num_blocks as usize,
+                nand.len() / BLOCK_SIZE,
+                spare.len() / SPARE_SIZE,
+            ));
+        }
+
+        let bulk = self.start_bulk("WriteNAND", num_blocks - start.min(num_blocks));
+        for block_num in (start..num_blocks).progress() {
+            let index = block_num as usize;
+            let outcome = self.write_block_spare(
+                &nand[index * BLOCK_SIZE..(index + 1) * BLOCK_SIZE],
+                &spare[index * SPARE_SIZE..(index + 1) * SPARE_SIZE],
+                block_num,
+                false,
+            )?;
+            on_block(block_num, outcome);
+            bulk.advance();
+        }
+        bulk.finish();
+        Ok(())
     }
 }
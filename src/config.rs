@@ -0,0 +1,141 @@
+use std::time::Duration;
+
+use crate::{
+    commands::LedState,
+    constants::{
+        BLOCK_ATTEMPTS, READY_MAX_POLL_INTERVAL, READY_MAX_WAIT, READY_POLL_INTERVAL, TIMEOUT,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutConfig {
+    pub command: Duration,
+    pub block_read: Duration,
+    pub block_write: Duration,
+    pub ready_wait: Duration,
+}
+
+impl TimeoutConfig {
+    pub fn uniform(timeout: Duration) -> Self {
+        Self {
+            command: timeout,
+            block_read: timeout,
+            block_write: timeout,
+            ready_wait: timeout,
+        }
+    }
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self::uniform(TIMEOUT)
+    }
+}
+
+// how wait_ready polls for the ready signal: the first check is immediate, then
+// the gap between checks starts at poll_interval and doubles up to max_interval.
+// it gives up with ReadyTimeout after max_wait. block writes (which may have to
+// erase first) and file checksums can keep the console busy for much longer,
+// so they wait up to slow_max_wait instead, which by default is forever. None
+// for either waits forever
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadyPolicy {
+    pub poll_interval: Duration,
+    pub max_interval: Duration,
+    pub max_wait: Option<Duration>,
+    pub slow_max_wait: Option<Duration>,
+}
+
+impl Default for ReadyPolicy {
+    fn default() -> Self {
+        Self {
+            poll_interval: READY_POLL_INTERVAL,
+            max_interval: READY_MAX_POLL_INTERVAL,
+            max_wait: Some(READY_MAX_WAIT),
+            slow_max_wait: None,
+        }
+    }
+}
+
+// how block reads and writes are retried: attempts includes the first try, and
+// backoff is slept before each retry. verify reads every written block back and
+// rewrites it if it doesn't match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub verify: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: BLOCK_ATTEMPTS,
+            backoff: Duration::ZERO,
+            verify: false,
+        }
+    }
+}
+
+// how much of each spare area WriteBlockAndSpare sends. Metadata sends the
+// first three bytes and pads the rest with 0xFF, so the NAND controller fills in
+// the ECC itself; Full sends all 16 bytes as given, ECC included, which a
+// restore needs to reproduce the original spare exactly
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SpareWriteMode {
+    #[default]
+    Metadata,
+    Full,
+}
+
+// what Init does with a console whose seqno shows another session (0 means
+// none has been started since it booted, 1 is what Init starts at). Reset
+// drains whatever that session left behind and starts a new one, Keep carries
+// on with it as it is, and Refuse fails with SessionInProgress
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionPolicy {
+    #[default]
+    Reset,
+    Keep,
+    Refuse,
+}
+
+// whether the cached FS is checked against the console before anything that
+// changes it. BeforeWrite reads the FS copy it was loaded from and the one the
+// next commit would go to (two block reads), and reloads the FS if either shows
+// a newer commit by something else; Never trusts the cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FsRefreshPolicy {
+    #[default]
+    BeforeWrite,
+    Never,
+}
+
+// the LED states long operations (dumps, restores and file writes) show while
+// they run and once they've finished or failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LedFeedback {
+    pub busy: LedState,
+    pub error: LedState,
+    pub done: LedState,
+}
+
+impl Default for LedFeedback {
+    fn default() -> Self {
+        Self {
+            busy: LedState::Power,
+            error: LedState::ErrorBlink,
+            done: LedState::Off,
+        }
+    }
+}
+
+// what WriteFileWith does when the file's already there. Overwrite replaces it
+// (or leaves it alone if it already has the same contents), which is what
+// WriteFile does; CreateNew refuses with FileAlreadyExists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WriteMode {
+    #[default]
+    Overwrite,
+    CreateNew,
+}
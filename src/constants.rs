@@ -9,12 +9,43 @@ pub(crate) const RDB_INTERFACE: u8 = 0;
 pub(crate) const RDB_BULK_EP_OUT: u8 = 0x02;
 pub(crate) const RDB_BULK_EP_IN: u8 = 0x82;
 
+#[cfg(feature = "rusb")]
+pub(crate) const VENDOR_SPECIFIC_CLASS: u8 = 0xFF;
+
 pub(crate) const BLOCK_SIZE: usize = 0x4000;
 pub(crate) const BLOCK_CHUNK_SIZE: usize = 0x1000;
 pub(crate) const SPARE_SIZE: usize = 0x10;
+// nothing the protocol does moves more than a block and its spare at once
+pub(crate) const REMOTE_MAX_TRANSFER: usize = BLOCK_SIZE + SPARE_SIZE;
+// the spare written with file data: not marked bad, no ECC of our own
+pub(crate) const BLANK_SPARE: [u8; SPARE_SIZE] = [0xFF; SPARE_SIZE];
 
 pub(crate) const TIMEOUT: Duration = Duration::SECOND;
+pub(crate) const READY_POLL_INTERVAL: Duration = Duration::from_millis(1);
+pub(crate) const READY_MAX_POLL_INTERVAL: Duration = Duration::from_millis(100);
+pub(crate) const READY_MAX_WAIT: Duration = Duration::from_secs(30);
+
+pub(crate) const BLOCK_ATTEMPTS: u32 = 5;
+pub(crate) const MAX_BLOCK_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+pub(crate) const RESYNC_TIMEOUT: Duration = Duration::from_millis(10);
+pub(crate) const DEBUG_CONSOLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+pub(crate) const REMOTE_LATENCY_ALLOWANCE: Duration = Duration::from_secs(2);
 
 pub(crate) const PACKET_SIZE: usize = 0x80;
 
 pub(crate) const SEND_CHUNK_SIZE: usize = 0x100;
+
+pub(crate) const HASH_SIZE: usize = 20;
+pub(crate) const SIGNATURE_SIZE: usize = 0x40;
+
+// the SK and system apps live in the first 0x40 blocks, and the 16 copies of
+// the filesystem in the last 16
+pub(crate) const SKSA_BLOCKS: u32 = 0x40;
+pub(crate) const FS_START_BLOCK: u32 = 0xFF0;
+
+// SA1's content metadata directly follows the four SK blocks
+pub(crate) const SA_CMD_BLOCK: u32 = 4;
+pub(crate) const CMD_CONTENT_ID_OFFSET: usize = 0x2898;
+
+#[cfg(feature = "rusb")]
+pub(crate) const HOTPLUG_POLL_INTERVAL: Duration = Duration::from_millis(500);
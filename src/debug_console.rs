@@ -0,0 +1,65 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    time::Duration,
+};
+
+use crate::{
+    constants::{DEBUG_CONSOLE_POLL_INTERVAL, PACKET_SIZE},
+    error::Result,
+    transport::Transport,
+    BBPlayer,
+};
+
+// homebrew on the console can print over the RDB channel; while no command is
+// in flight, anything arriving on the bulk IN endpoint is its output
+#[derive(Debug)]
+pub struct DebugConsole<'a, B: Transport> {
+    player: &'a BBPlayer<B>,
+    pending: VecDeque<u8>,
+}
+
+impl<B: Transport> BBPlayer<B> {
+    pub fn debug_console(&self) -> DebugConsole<'_, B> {
+        DebugConsole {
+            player: self,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<B: Transport> DebugConsole<'_, B> {
+    // returns whatever arrived within the timeout, which may be nothing
+    pub fn poll(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        let buf = match self.player.bulk_transfer_receive(PACKET_SIZE, timeout) {
+            Ok(buf) => buf,
+            Err(e) if e.is_timeout() => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        // the console announcing it's ready for a command isn't output; keep
+        // track of it so the next command doesn't wait for it again
+        if buf == BBPlayer::<B>::READY_SIGNAL {
+            self.player.ready_pending.set(true);
+            return Ok(vec![]);
+        }
+        Ok(buf)
+    }
+}
+
+impl<B: Transport> Read for DebugConsole<'_, B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            let data = self
+                .poll(DEBUG_CONSOLE_POLL_INTERVAL)
+                .map_err(io::Error::other)?;
+            self.pending.extend(data);
+        }
+
+        let len = buf.len().min(self.pending.len());
+        for (dst, src) in buf.iter_mut().zip(self.pending.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
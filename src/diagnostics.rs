@@ -0,0 +1,125 @@
+use std::fmt::Write;
+
+use rusb::{request_type, Device, Direction, Recipient, RequestType, UsbContext};
+
+use crate::{
+    constants::TIMEOUT,
+    error::{wrap_libusb_error, Result},
+    usb::UsbTransport,
+    BBPlayer,
+};
+
+const GET_STATUS: u8 = 0x00;
+const CLEAR_FEATURE: u8 = 0x01;
+
+pub const ENDPOINT_HALT: u16 = 0x00;
+
+impl<C: UsbContext> BBPlayer<UsbTransport<C>> {
+    // the raw GET_STATUS word for an endpoint; bit 0 is the halt flag
+    pub fn endpoint_status(&self, endpoint: u8) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        wrap_libusb_error(self.transport.handle.read_control(
+            request_type(Direction::In, RequestType::Standard, Recipient::Endpoint),
+            GET_STATUS,
+            0,
+            endpoint as u16,
+            &mut buf,
+            TIMEOUT,
+        ))?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn endpoint_halted(&self, endpoint: u8) -> Result<bool> {
+        Ok(self.endpoint_status(endpoint)? & 1 != 0)
+    }
+
+    pub fn clear_feature(&self, endpoint: u8, feature: u16) -> Result<()> {
+        wrap_libusb_error(self.transport.handle.write_control(
+            request_type(Direction::Out, RequestType::Standard, Recipient::Endpoint),
+            CLEAR_FEATURE,
+            feature,
+            endpoint as u16,
+            &[],
+            TIMEOUT,
+        ))?;
+        Ok(())
+    }
+
+    pub fn dump_descriptors(&self) -> Result<String> {
+        dump_descriptors(&self.transport.handle.device())
+    }
+}
+
+// a human-readable dump of the device, configuration, interface and endpoint
+// descriptors, suitable for pasting into a bug report
+pub fn dump_descriptors<C: UsbContext>(device: &Device<C>) -> Result<String> {
+    let desc = wrap_libusb_error(device.device_descriptor())?;
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "Device {:04x}:{:04x} (bus {}, address {}, speed {:?})",
+        desc.vendor_id(),
+        desc.product_id(),
+        device.bus_number(),
+        device.address(),
+        device.speed()
+    );
+    let _ = writeln!(
+        out,
+        "  USB {}, class {:02x}/{:02x}/{:02x}, max packet size {}, device version {}, {} configuration(s)",
+        desc.usb_version(),
+        desc.class_code(),
+        desc.sub_class_code(),
+        desc.protocol_code(),
+        desc.max_packet_size(),
+        desc.device_version(),
+        desc.num_configurations()
+    );
+
+    for index in 0..desc.num_configurations() {
+        let config = match device.config_descriptor(index) {
+            Ok(c) => c,
+            Err(e) => {
+                let _ = writeln!(out, "  Configuration #{index}: {e}");
+                continue;
+            }
+        };
+        let _ = writeln!(
+            out,
+            "  Configuration {}: {} interface(s), max power {} mA, self powered {}, remote wakeup {}",
+            config.number(),
+            config.num_interfaces(),
+            config.max_power(),
+            config.self_powered(),
+            config.remote_wakeup()
+        );
+        for interface in config.interfaces() {
+            for alt in interface.descriptors() {
+                let _ = writeln!(
+                    out,
+                    "    Interface {} alt {}: class {:02x}/{:02x}/{:02x}, {} endpoint(s)",
+                    alt.interface_number(),
+                    alt.setting_number(),
+                    alt.class_code(),
+                    alt.sub_class_code(),
+                    alt.protocol_code(),
+                    alt.num_endpoints()
+                );
+                for ep in alt.endpoint_descriptors() {
+                    let _ = writeln!(
+                        out,
+                        "      Endpoint {:02x}: {:?} {:?}, max packet size {}, interval {}",
+                        ep.address(),
+                        ep.direction(),
+                        ep.transfer_type(),
+                        ep.max_packet_size(),
+                        ep.interval()
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(out)
+}
@@ -1,32 +1,104 @@
+use std::{path::PathBuf, time::Duration};
+
 use thiserror::Error;
 
 use crate::commands::Command;
+use crate::constants::{BB_PRODUCT_ID, BLOCK_SIZE, IQUE_VENDOR_ID};
 
 use crate::player_comms::TransferCommand;
 
 #[derive(Error, Debug)]
 pub enum LibBBError {
+    #[cfg(feature = "rusb")]
     #[error("libusb error: {0}")]
     LibUSBError(#[from] rusb::Error),
 
+    #[cfg(feature = "nusb")]
+    #[error("nusb transfer error: {0}")]
+    NusbTransfer(#[from] nusb::transfer::TransferError),
+
+    #[error("USB transfer timed out")]
+    Timeout,
+
+    #[error("Console did not signal ready within {0:?}")]
+    ReadyTimeout(Duration),
+
+    #[error("The player's worker thread has stopped")]
+    WorkerStopped,
+
+    #[error("Remote transport error: {0}")]
+    Remote(String),
+
+    #[error("Unexpected remote transport message {0:02X}")]
+    RemoteProtocol(u8),
+
     #[error("binrw error: {0}")]
     BinRWError(#[from] binrw::Error),
 
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[cfg(feature = "async")]
+    #[error("Background task failed: {0}")]
+    Join(#[from] tokio::task::JoinError),
+
+    #[error("No console with BBID {0:04X} is connected")]
+    NoPlayerWithBBID(u32),
+
     #[error("Device not initialised. Did you call Init?")]
     NoConsole,
 
+    #[error("No console found on bus {0}, port path {1:?}")]
+    PlayerGone(u8, Vec<u8>),
+
+    #[error("The console is in another session (seqno {0})")]
+    SessionInProgress(u32),
+
+    #[error("Expected seqno {0}, the console has {1}")]
+    SeqNoMismatch(u32, u32),
+
     #[error("No valid filesystem found.")]
     FS,
 
-    #[error("Failed to read block {0} after 5 attempts")]
-    ReadBlock(u32),
+    #[error("Failed to read block {0} after {1} attempts")]
+    ReadBlock(u32, u32),
 
-    #[error("Failed to write block {0} after 5 attempts")]
-    WriteBlock(u32),
+    #[error("Received a short block; expected {0} bytes, got {1}")]
+    ShortBlock(usize, usize),
+
+    #[error("Failed to write block {0} after {1} attempts")]
+    WriteBlock(u32, u32),
+
+    #[error("Block {0} read back differently from what was written")]
+    VerifyFailed(u32),
+
+    #[error("The console's firmware doesn't support {0:?}")]
+    Unsupported(Command),
 
     #[error("Command {0:?} returned {1}")]
     Command(Command, i32),
 
+    #[error("Block {0} is out of range; the console has {1} blocks")]
+    BlockOutOfRange(u32, u32),
+
+    #[error("Block {0} is in the SKSA or filesystem area; writing it needs allow_system_blocks")]
+    SystemBlock(u32),
+
+    #[error("Block {0} is not one of the filesystem copies")]
+    NotAnFSCopy(u32),
+
+    #[error("The filesystem copy in block {0} is corrupt")]
+    CorruptFSCopy(u32),
+
+    #[error("Block data must be {0} bytes long, got {1}")]
+    BadBlockLength(usize, usize),
+
+    #[error("Spare data must be {0} bytes long, got {1}")]
+    BadSpareLength(usize, usize),
+
+    // the console reports this, but it's the NAND program failing rather than
+    // the command being refused, and a rewrite of the same block often takes.
+    // write_block_spare always retried it, so it's classed as transient
     #[error("Write block: returned {0} (error)")]
     CheckBlockWrite(i32),
 
@@ -39,6 +111,9 @@ pub enum LibBBError {
     #[error("Get BBID: returned {0} (error)")]
     GetBBID(i32),
 
+    #[error("Malformed reply to {0:?}; expected {1} bytes, got {2}")]
+    MalformedReply(Command, usize, usize),
+
     #[error("Expected transfer length {0}, got {1}")]
     TransferLength(usize, usize),
 
@@ -51,6 +126,21 @@ pub enum LibBBError {
     #[error("The device has an incorrect descriptor active")]
     IncorrectDescriptor,
 
+    #[error("Interface {} is bound to {}. Open with force_detach to detach it, or blacklist the driver or add a udev rule so the console is left unbound", .0, if let Some(d) = .1 {format!("the kernel driver {d}")} else {"a kernel driver".to_string()})]
+    KernelDriverBound(u8, Option<String>),
+
+    #[error("No permission to open the console ({0:04x}:{1:04x}). On Linux, install a udev rule such as the following in /etc/udev/rules.d/ and replug the console:\n{2}")]
+    Permissions(u16, u16, String),
+
+    #[error("No WinUSB driver is installed for the console ({0}). Install WinUSB for it with Zadig and try again")]
+    DriverMissing(String),
+
+    #[error("Console is in use by another process (lock file {0})")]
+    Busy(PathBuf),
+
+    #[error("Interface {} is already claimed{}. Close any other program using the console and try again", .0, if let Some(d) = .1 {format!(" by {d}")} else {"".to_string()})]
+    InterfaceBusy(u8, Option<String>),
+
     #[error("Incorrect data length reply received; expected 4 bytes beginning 0x1B, received {} byte{}{}", .1, if .1 != &1 {"s"} else {""}, if let Some(b) = .0 {format!(" beginning 0x{:02X}", b)} else {"".to_string()})]
     IncorrectDataLengthReply(Option<u8>, usize),
 
@@ -65,12 +155,30 @@ pub enum LibBBError {
     #[error("Provided filename ({0}) is an invalid CString. Does it contain null bytes (0x00)?")]
     FileNameCString(String),
 
+    #[error("Provided filename ({0}) is not a valid 8.3 DOS filename")]
+    InvalidFileName(String),
+
     #[error("No FS block found. Did the console initialise properly?")]
     NoFSBlock,
 
     #[error("File {0} not found on the console")]
     FileNotFound(String),
 
+    #[error("Some of {0}'s blocks have been reused, so it can't be recovered")]
+    BlocksReused(String),
+
+    #[error("File {0} is in the batch more than once")]
+    DuplicateFileName(String),
+
+    #[error("File {0} already exists on the console")]
+    FileAlreadyExists(String),
+
+    #[error("File {0} has an invalid block chain (block 0x{1:04X} is outside the data area)")]
+    InvalidStartBlock(String, u16),
+
+    #[error("File {0} is {1} bytes long, but its block chain only holds {2} block{}", if .2 != &1 {"s"} else {""})]
+    FileSizeExceedsChain(String, u32, usize),
+
     #[error("Trying to write an invalid number of blocks; expected {} block{}, counted {}, trying to write {}", .0, if .0 != &1 {"s"} else {""}, .1, .2)]
     IncorrectNumBlocks(usize, usize, usize),
 
@@ -80,12 +188,171 @@ pub enum LibBBError {
     #[error("There are not enough blocks free on the console. Try deleting some files to free up space.")]
     NoFreeBlocks,
 
+    #[error("There is not enough space on the console for this file; it needs {0} block{}, but only {1} {} free. Try deleting some files to free up space.", if .0 != &1 {"s"} else {""}, if .1 != &1 {"are"} else {"is"})]
+    InsufficientSpace(usize, usize),
+
+    #[error("NAND image does not match the console; expected {} block{}, got {} block{} of data and {} block{} of spare", .0, if .0 != &1 {"s"} else {""}, .1, if .1 != &1 {"s"} else {""}, .2, if .2 != &1 {"s"} else {""})]
+    IncorrectNANDSize(usize, usize, usize),
+
+    #[error("Operation cancelled after {} block{}", .0.len() / BLOCK_SIZE, if .0.len() != BLOCK_SIZE {"s"} else {""})]
+    Cancelled(Vec<u8>, Vec<u8>),
+
+    #[error("Wipe cancelled after {} block{}", .0, if .0 != &1 {"s"} else {""})]
+    WipeCancelled(u32),
+
+    #[error("The console's FS changed while {0} was being written; write it again")]
+    FSChanged(String),
+
     #[error("Failed to verify file {0} (expected checksum {1:08X}")]
     ChecksumFailed(String, u32),
 }
 
 pub type Result<T> = std::result::Result<T, LibBBError>;
 
+pub fn udev_rule() -> String {
+    format!(
+        "SUBSYSTEM==\"usb\", ATTRS{{idVendor}}==\"{IQUE_VENDOR_ID:04x}\", ATTRS{{idProduct}}==\"{BB_PRODUCT_ID:04x}\", MODE=\"0660\", TAG+=\"uaccess\""
+    )
+}
+
+// what the console's negative return codes mean. the filesystem commands use
+// the BBFS error codes from the iQue OS; the block commands only ever report
+// that the NAND operation failed, which is an uncorrectable ECC error on a read
+// or a block going bad on a write
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleError {
+    #[error("NAND operation failed ({0})")]
+    Nand(i32),
+
+    #[error("not enough space on the filesystem")]
+    NoSpace,
+
+    #[error("invalid argument")]
+    InvalidArgument,
+
+    #[error("file already exists")]
+    Exists,
+
+    #[error("operation failed")]
+    Failed,
+
+    #[error("no such file")]
+    NotFound,
+
+    #[error("unknown error {0}")]
+    Unknown(i32),
+}
+
+impl ConsoleError {
+    pub fn from_code(command: Command, code: i32) -> Self {
+        match (command, code) {
+            (
+                Command::ReadBlock
+                | Command::WriteBlock
+                | Command::ReadBlockAndSpare
+                | Command::WriteBlockAndSpare,
+                _,
+            ) => Self::Nand(code),
+            (_, -1) => Self::NoSpace,
+            (_, -2) => Self::InvalidArgument,
+            (_, -3) => Self::Exists,
+            (_, -4) => Self::Failed,
+            (_, -5) => Self::NotFound,
+            _ => Self::Unknown(code),
+        }
+    }
+}
+
+pub(crate) fn permissions_error() -> LibBBError {
+    LibBBError::Permissions(IQUE_VENDOR_ID, BB_PRODUCT_ID, udev_rule())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    // worth retrying after a resync: timeouts and garbled or short replies
+    Transient,
+    // an endpoint is halted and needs clearing (see BBPlayer::Recover)
+    Stall,
+    // the console has been unplugged or the connection dropped
+    Gone,
+    Fatal,
+}
+
+impl LibBBError {
+    pub fn class(&self) -> ErrorClass {
+        if self.is_timeout() {
+            return ErrorClass::Transient;
+        }
+        if self.is_disconnect() {
+            return ErrorClass::Gone;
+        }
+        match self {
+            #[cfg(feature = "rusb")]
+            Self::LibUSBError(rusb::Error::Pipe) => ErrorClass::Stall,
+            #[cfg(feature = "rusb")]
+            Self::LibUSBError(rusb::Error::Overflow | rusb::Error::Interrupted) => {
+                ErrorClass::Transient
+            }
+            #[cfg(feature = "nusb")]
+            Self::NusbTransfer(nusb::transfer::TransferError::Stall) => ErrorClass::Stall,
+            Self::ShortBlock(..)
+            | Self::CheckBlockWrite(_)
+            | Self::TransferLength(..)
+            | Self::MalformedReply(..)
+            | Self::PiecemealChunkTooShort(..)
+            | Self::UnexpectedPiecemealChunkType(_)
+            | Self::IncorrectDataLengthReply(..)
+            | Self::InvalidReplyLength(..) => ErrorClass::Transient,
+            _ => ErrorClass::Fatal,
+        }
+    }
+
+    // the decoded cause, for errors that came from a negative return code
+    pub fn console_error(&self) -> Option<ConsoleError> {
+        let (command, code) = match *self {
+            Self::Command(command, code) => (command, code),
+            Self::CheckBlockWrite(code) => (Command::WriteBlockAndSpare, code),
+            Self::InitFS(code) => (Command::InitFS, code),
+            Self::SetTime(code) => (Command::SetTime, code),
+            Self::GetBBID(code) => (Command::GetBBID, code),
+            _ => return None,
+        };
+        Some(ConsoleError::from_code(command, code))
+    }
+
+    pub fn is_transient(&self) -> bool {
+        self.class() == ErrorClass::Transient
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            #[cfg(feature = "rusb")]
+            Self::LibUSBError(rusb::Error::Timeout) => true,
+            Self::Timeout => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            #[cfg(feature = "rusb")]
+            Self::LibUSBError(rusb::Error::NoDevice) => true,
+            #[cfg(feature = "nusb")]
+            Self::NusbTransfer(nusb::transfer::TransferError::Disconnected) => true,
+            Self::Io(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::NotConnected
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "rusb")]
 pub(crate) fn wrap_libusb_error<T>(value: rusb::Result<T>) -> Result<T> {
     match value {
         Ok(v) => Ok(v),
@@ -0,0 +1,74 @@
+use std::{
+    fmt,
+    sync::{
+        mpsc::{channel, Receiver},
+        Arc,
+    },
+};
+
+use crate::{error::LibBBError, transport::Transport, BBPlayer};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    DeviceAttached,
+    DeviceDetached,
+    OperationStarted {
+        operation: &'static str,
+        total: u32,
+    },
+    OperationProgress {
+        operation: &'static str,
+        done: u32,
+        total: u32,
+    },
+    OperationFinished {
+        operation: &'static str,
+        success: bool,
+    },
+    Retry {
+        attempt: u32,
+        attempts: u32,
+    },
+    Warning(String),
+}
+
+// called with the block number, which attempt just failed (counting from 1) and
+// why, whenever a block operation hits an error it would retry; returning false
+// gives up and returns that error instead
+pub type RetryHook = dyn Fn(u32, u32, &LibBBError) -> bool + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct RetryHookHandle(pub(crate) Arc<RetryHook>);
+
+impl fmt::Debug for RetryHookHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RetryHook")
+    }
+}
+
+impl<B: Transport> BBPlayer<B> {
+    pub fn on_retry<F: Fn(u32, u32, &LibBBError) -> bool + Send + Sync + 'static>(
+        &mut self,
+        hook: F,
+    ) {
+        self.on_retry = Some(RetryHookHandle(Arc::new(hook)));
+    }
+
+    pub fn clear_on_retry(&mut self) {
+        self.on_retry = None;
+    }
+
+    // each call returns a new subscription; events are delivered to every
+    // receiver still alive, and dropped receivers are forgotten
+    pub fn events(&self) -> Receiver<Event> {
+        let (tx, rx) = channel();
+        self.subscribers.borrow_mut().push(tx);
+        rx
+    }
+
+    pub(crate) fn emit(&self, event: Event) {
+        self.subscribers
+            .borrow_mut()
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
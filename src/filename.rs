@@ -0,0 +1,97 @@
+use std::{fmt, str::FromStr};
+
+use crate::error::{LibBBError, Result};
+
+// the same characters DOS would take in an 8.3 name
+const INVALID_CHARS: &[u8] = b"\"*+,/:;<=>?[\\]|";
+
+// an 8.3 name that's already been checked, so the file commands can reject a
+// bad one before sending anything. names are stored in lower case, which is
+// how the console's own files are named
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FatFilename {
+    full: String,
+    dot: Option<usize>,
+}
+
+impl FatFilename {
+    pub fn new<T: AsRef<str>>(filename: T) -> Result<Self> {
+        let filename = filename.as_ref();
+        let invalid = || LibBBError::InvalidFileName(filename.to_string());
+
+        let (name, ext) = match filename.split_once('.') {
+            Some((_, ext)) if ext.is_empty() || ext.contains('.') => return Err(invalid()),
+            Some((name, ext)) => (name, Some(ext)),
+            None => (filename, None),
+        };
+        if name.is_empty()
+            || !filename
+                .bytes()
+                .all(|c| c == b'.' || (c.is_ascii_graphic() && !INVALID_CHARS.contains(&c)))
+        {
+            return Err(invalid());
+        }
+        if name.len() > 8 || ext.is_some_and(|ext| ext.len() > 3) {
+            return Err(LibBBError::FileNameTooLong(filename.to_string()));
+        }
+
+        Ok(Self {
+            full: filename.to_ascii_lowercase(),
+            dot: ext.map(|_| name.len()),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.full
+    }
+
+    pub fn name(&self) -> &str {
+        match self.dot {
+            Some(dot) => &self.full[..dot],
+            None => &self.full,
+        }
+    }
+
+    pub fn ext(&self) -> &str {
+        match self.dot {
+            Some(dot) => &self.full[dot + 1..],
+            None => "",
+        }
+    }
+}
+
+impl AsRef<str> for FatFilename {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for FatFilename {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.full)
+    }
+}
+
+impl FromStr for FatFilename {
+    type Err = LibBBError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<&str> for FatFilename {
+    type Error = LibBBError;
+
+    fn try_from(value: &str) -> Result<Self> {
+        Self::new(value)
+    }
+}
+
+impl TryFrom<String> for FatFilename {
+    type Error = LibBBError;
+
+    fn try_from(value: String) -> Result<Self> {
+        Self::new(value)
+    }
+}
@@ -1,14 +1,20 @@
 use std::{
     ffi::CString,
     io::{Cursor, Seek},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use crate::{
-    constants::{BLOCK_SIZE, SPARE_SIZE},
+    config::FsRefreshPolicy,
+    constants::{BLANK_SPARE, BLOCK_SIZE, FS_START_BLOCK, SKSA_BLOCKS, SPARE_SIZE},
     error::{LibBBError, Result},
-    num_from_arr, BBPlayer,
+    filename::FatFilename,
+    num_from_arr,
+    transport::Transport,
+    BBPlayer,
 };
 use indicatif::{ProgressBar, ProgressStyle};
+use log::warn;
 
 use binrw::{binrw, BinReaderExt, BinResult, BinWriterExt};
 
@@ -27,7 +33,7 @@ pub enum FATEntry {
 }
 
 #[binrw]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum FileValid {
     #[brw(magic = 0x00u8)]
     Invalid,
@@ -36,7 +42,7 @@ pub enum FileValid {
 }
 
 #[binrw]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FileEntry {
     name: [u8; 8],
     ext: [u8; 3],
@@ -47,7 +53,7 @@ pub struct FileEntry {
 }
 
 #[binrw]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum FSType {
     #[brw(magic = b"BBFS")]
     Bbfs,
@@ -56,7 +62,7 @@ pub enum FSType {
 }
 
 #[binrw]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FSFooter {
     fs_type: FSType,
     seqno: u32,
@@ -64,8 +70,177 @@ pub struct FSFooter {
     chksum: u16,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainLink {
+    pub block: u16,
+    pub contiguous: bool,
+}
+
+// a directory listing entry. blocks is how long the file's chain actually is,
+// and valid is whether that chain stays in the data area, ends properly and is
+// as long as the size says it should be
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    pub name: String,
+    pub size: u32,
+    pub start_block: Option<u16>,
+    pub blocks: usize,
+    pub valid: bool,
+}
+
+// totals over the whole FAT. reserved and system blocks count as used, the
+// same as the console reports them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FsStats {
+    pub free_blocks: usize,
+    pub used_blocks: usize,
+    pub bad_blocks: usize,
+    pub seqno: u32,
+    pub total_bytes_free: usize,
+    pub total_bytes_used: usize,
+}
+
+// something CheckFS found wrong. block is where the file's chain goes wrong:
+// a link outside the data area, a block marked bad, one whose FAT entry is
+// free or reserved rather than a link, or one already seen earlier in the same
+// chain (Loop) or in another file's (CrossLinked)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsProblem {
+    OutOfRange {
+        file: String,
+        block: u16,
+    },
+    BadBlock {
+        file: String,
+        block: u16,
+    },
+    Unterminated {
+        file: String,
+        block: u16,
+    },
+    Loop {
+        file: String,
+        block: u16,
+    },
+    CrossLinked {
+        block: u16,
+        files: (String, String),
+    },
+    SizeMismatch {
+        file: String,
+        size: u32,
+        blocks: usize,
+    },
+    LostBlocks(Vec<u16>),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsckReport {
+    pub problems: Vec<FsProblem>,
+    pub repaired: bool,
+}
+
+// what each block is being used for, going by the FAT. System covers the SKSA
+// and the FS copies themselves, and Lost is a block that's allocated but that
+// no file's chain reaches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockState {
+    Free,
+    Reserved,
+    Bad,
+    System,
+    Lost,
+    // an index into FatView::files
+    File(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappedFile {
+    pub info: FileInfo,
+    pub chain: Vec<u16>,
+}
+
+// blocks has an entry for every block the FAT covers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FatView {
+    pub blocks: Vec<BlockState>,
+    pub files: Vec<MappedFile>,
+    pub free: usize,
+    pub used: usize,
+    pub bad: usize,
+    pub reserved: usize,
+    pub lost: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoverySource {
+    // the file's entry is still in an older copy of the FS
+    Generation { block: u32, seqno: u32 },
+    // allocated blocks that no file refers to, like the remains of an
+    // interrupted write; these get named after their first block
+    OrphanChain,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoverableFile {
+    pub name: String,
+    pub size: u32,
+    pub blocks: Vec<u16>,
+    pub source: RecoverySource,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DefragReport {
+    pub moved: Vec<(String, usize)>,
+    pub skipped: Vec<String>,
+}
+
+// a file's name, its current chain and the run it's being moved to
+type DefragMove = (String, Vec<u16>, Vec<u16>);
+
+// a fragment is a run of consecutive blocks, so an unfragmented file has one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileFragmentation {
+    pub name: String,
+    pub blocks: usize,
+    pub fragments: usize,
+    pub longest_run: usize,
+}
+
+// score is the share of links between one block of a file and the next that
+// aren't to the following block: 0.0 when every file is contiguous, 1.0 when
+// no two blocks of any file are adjacent
+#[derive(Debug, Clone, PartialEq)]
+pub struct FragmentationReport {
+    pub files: Vec<FileFragmentation>,
+    pub fragmented_files: usize,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsCopy {
+    pub block: u32,
+    pub seqno: u32,
+    pub magic_valid: bool,
+    pub checksum_valid: bool,
+    // the one the cached FS was loaded from
+    pub current: bool,
+}
+
+// how far a ResumeWrite got: the blocks picked for the file, in chain order,
+// and how many of them hold their data. nothing refers to these blocks until
+// the upload completes, so keep this around (it's plain data, so it can be
+// saved) and hand it back to carry on after a failure
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteProgress {
+    pub blocks: Vec<u16>,
+    pub written: usize,
+    pub chksum: u32,
+}
+
 #[binrw]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct FSBlock {
     fat: [FATEntry; 0x1000],
     entries: [FileEntry; 409],
@@ -73,15 +248,22 @@ pub(crate) struct FSBlock {
 }
 
 impl FSBlock {
+    fn checksum_valid(data: &[u8]) -> bool {
+        data.chunks(2).fold(0u16, |a, e| match e {
+            &[upper, lower] => a.wrapping_add(u16::from_be_bytes([upper, lower])),
+            _ => unreachable!(),
+        }) == 0xCAD7
+    }
+
+    fn magic_valid(data: &[u8]) -> bool {
+        matches!(&data[0x3FF4..0x3FF8], b"BBFS" | b"BBFL")
+    }
+
     fn read<T: AsRef<[u8]>>(data: T) -> BinResult<Self> {
         let mut cursor = Cursor::new(data.as_ref());
         match <_>::read_be(&mut cursor) {
             Ok(fs) => {
-                if data.as_ref().chunks(2).fold(0u16, |a, e| match e {
-                    &[upper, lower] => a.wrapping_add(u16::from_be_bytes([upper, lower])),
-                    _ => unreachable!(),
-                }) != 0xCAD7
-                {
+                if !Self::checksum_valid(data.as_ref()) {
                     Err(binrw::Error::AssertFail {
                         pos: 0x3FFE,
                         message: "Invalid checksum".to_string(),
@@ -94,6 +276,267 @@ impl FSBlock {
         }
     }
 
+    fn files(&self) -> Files<'_> {
+        Files {
+            block: self,
+            entries: self.entries.iter(),
+        }
+    }
+
+    fn file_info(&self, entry: &FileEntry) -> FileInfo {
+        let mut blocks = 0;
+        let mut next_block = entry.start;
+        // a chain can't be longer than the FAT, so anything that gets that far
+        // has looped back on itself
+        let ended = loop {
+            match next_block {
+                FATEntry::Chain(b) if in_data_area(b) && blocks < 0x1000 => {
+                    blocks += 1;
+                    next_block = self.fat[b as usize];
+                }
+                FATEntry::EndOfChain => break true,
+                _ => break false,
+            }
+        };
+        FileInfo {
+            name: entry.get_fullname(),
+            size: entry.size,
+            start_block: match entry.start {
+                FATEntry::Chain(b) => Some(b),
+                _ => None,
+            },
+            blocks,
+            valid: ended && blocks == (entry.size as usize).div_ceil(BLOCK_SIZE),
+        }
+    }
+
+    // a block that's already someone else's ends the chain there, so a
+    // cross-linked block is only counted against the first file to reach it
+    fn fat_view(&self) -> FatView {
+        let mut blocks = self
+            .fat
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| match entry {
+                _ if !in_data_area(index as u32) => BlockState::System,
+                FATEntry::Free => BlockState::Free,
+                FATEntry::Reserved => BlockState::Reserved,
+                FATEntry::BadBlock => BlockState::Bad,
+                FATEntry::Chain(_) | FATEntry::EndOfChain => BlockState::Lost,
+            })
+            .collect::<Vec<_>>();
+
+        let mut files = vec![];
+        for entry in self.entries.iter().filter(|e| e.valid()) {
+            let mut chain = vec![];
+            let mut next_block = entry.start;
+            while let FATEntry::Chain(b) = next_block
+                && blocks.get(b as usize) == Some(&BlockState::Lost)
+            {
+                blocks[b as usize] = BlockState::File(files.len());
+                chain.push(b);
+                next_block = self.fat[b as usize];
+            }
+            files.push(MappedFile {
+                info: self.file_info(entry),
+                chain,
+            });
+        }
+
+        let count = |state: BlockState| blocks.iter().filter(|&&b| b == state).count();
+        FatView {
+            free: count(BlockState::Free),
+            used: blocks
+                .iter()
+                .filter(|b| matches!(b, BlockState::File(_)))
+                .count(),
+            bad: count(BlockState::Bad),
+            reserved: count(BlockState::Reserved),
+            lost: count(BlockState::Lost),
+            blocks,
+            files,
+        }
+    }
+
+    fn fragmentation(&self) -> FragmentationReport {
+        let mut links = 0;
+        let mut breaks = 0;
+        let files = self
+            .fat_view()
+            .files
+            .into_iter()
+            .map(|file| {
+                let mut fragments = 0;
+                let mut longest_run = 0;
+                let mut run = 0;
+                for (index, &b) in file.chain.iter().enumerate() {
+                    if index > 0 && file.chain[index - 1].wrapping_add(1) == b {
+                        run += 1;
+                    } else {
+                        fragments += 1;
+                        run = 1;
+                    }
+                    longest_run = longest_run.max(run);
+                }
+                links += file.chain.len().saturating_sub(1);
+                breaks += fragments.max(1) - 1;
+                FileFragmentation {
+                    name: file.info.name,
+                    blocks: file.chain.len(),
+                    fragments,
+                    longest_run,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        FragmentationReport {
+            fragmented_files: files.iter().filter(|f| f.fragments > 1).count(),
+            score: if links == 0 {
+                0.0
+            } else {
+                breaks as f64 / links as f64
+            },
+            files,
+        }
+    }
+
+    // the chain as this FS had it, as long as it stays in the data area and is
+    // long enough for the file's size
+    fn chain_of(&self, entry: &FileEntry) -> Option<Vec<u16>> {
+        let required_blocks = (entry.size as usize).div_ceil(BLOCK_SIZE).max(1);
+        let mut chain = Vec::with_capacity(required_blocks);
+        let mut next_block = entry.start;
+        while chain.len() < required_blocks {
+            match next_block {
+                FATEntry::Chain(b) if in_data_area(b) && !chain.contains(&b) => {
+                    chain.push(b);
+                    next_block = self.fat[b as usize];
+                }
+                _ => return None,
+            }
+        }
+        Some(chain)
+    }
+
+    fn orphan_chains(&self) -> Vec<Vec<u16>> {
+        let lost = match self.clone().check(false).pop() {
+            Some(FsProblem::LostBlocks(lost)) => lost,
+            _ => return vec![],
+        };
+        let pointed_at = lost
+            .iter()
+            .filter_map(|&b| match self.fat[b as usize] {
+                FATEntry::Chain(next) => Some(next),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut chains = vec![];
+        for &head in lost.iter().filter(|b| !pointed_at.contains(b)) {
+            let mut chain = vec![head];
+            while let FATEntry::Chain(next) = self.fat[chain[chain.len() - 1] as usize]
+                && lost.contains(&next)
+                && !chain.contains(&next)
+            {
+                chain.push(next);
+            }
+            chains.push(chain);
+        }
+        chains
+    }
+
+    // with repair set, each chain is cut off just before where it goes wrong (a
+    // file with nothing left is deleted), sizes are made to fit their chains,
+    // and blocks nothing refers to are freed
+    fn check(&mut self, repair: bool) -> Vec<FsProblem> {
+        let mut problems = vec![];
+        let mut owner: Vec<Option<usize>> = vec![None; self.fat.len()];
+
+        for index in 0..self.entries.len() {
+            if !self.entries[index].valid() {
+                continue;
+            }
+            let file = self.entries[index].get_fullname();
+            let mut chain: Vec<u16> = vec![];
+            let mut next_block = self.entries[index].start;
+            let broken = loop {
+                let b = match next_block {
+                    FATEntry::EndOfChain => break false,
+                    FATEntry::Chain(b) => b,
+                    _ => {
+                        let block = chain.last().copied().unwrap_or(0);
+                        problems.push(FsProblem::Unterminated { file, block });
+                        break true;
+                    }
+                };
+                let problem = if !in_data_area(b) {
+                    FsProblem::OutOfRange { file, block: b }
+                } else if chain.contains(&b) {
+                    FsProblem::Loop { file, block: b }
+                } else if let Some(other) = owner[b as usize] {
+                    let files = (self.entries[other].get_fullname(), file);
+                    FsProblem::CrossLinked { block: b, files }
+                } else if self.fat[b as usize] == FATEntry::BadBlock {
+                    FsProblem::BadBlock { file, block: b }
+                } else {
+                    owner[b as usize] = Some(index);
+                    chain.push(b);
+                    next_block = self.fat[b as usize];
+                    continue;
+                };
+                problems.push(problem);
+                break true;
+            };
+
+            let file = self.entries[index].get_fullname();
+            let size = self.entries[index].size;
+            let needed = (size as usize).div_ceil(BLOCK_SIZE).max(1);
+            if chain.len() != needed && !(broken && chain.is_empty()) {
+                problems.push(FsProblem::SizeMismatch {
+                    file,
+                    size,
+                    blocks: chain.len(),
+                });
+            }
+
+            if !repair {
+                continue;
+            }
+            if chain.is_empty() {
+                self.entries[index].clear();
+                continue;
+            }
+            if chain.len() > needed {
+                for &b in &chain[needed..] {
+                    owner[b as usize] = None;
+                }
+                chain.truncate(needed);
+            }
+            let entry = &mut self.entries[index];
+            entry.size = entry.size.min((chain.len() * BLOCK_SIZE) as u32);
+            self.fat[chain[chain.len() - 1] as usize] = FATEntry::EndOfChain;
+        }
+
+        let lost = (SKSA_BLOCKS as u16..FS_START_BLOCK as u16)
+            .filter(|&b| {
+                matches!(
+                    self.fat[b as usize],
+                    FATEntry::Chain(_) | FATEntry::EndOfChain
+                ) && owner[b as usize].is_none()
+            })
+            .collect::<Vec<_>>();
+        if !lost.is_empty() {
+            if repair {
+                for &b in &lost {
+                    self.fat[b as usize] = FATEntry::Free;
+                }
+            }
+            problems.push(FsProblem::LostBlocks(lost));
+        }
+
+        problems
+    }
+
     fn write(&self) -> BinResult<Vec<u8>> {
         let mut cursor = Cursor::new(vec![]);
         match cursor.write_be(self) {
@@ -113,22 +556,113 @@ impl FSBlock {
     }
 }
 
+// the files in an FS block, one at a time
+#[derive(Debug, Clone)]
+pub struct Files<'a> {
+    block: &'a FSBlock,
+    entries: std::slice::Iter<'a, FileEntry>,
+}
+
+impl Iterator for Files<'_> {
+    type Item = FileInfo;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.find(|e| e.valid())?;
+        Some(self.block.file_info(entry))
+    }
+}
+
+// an FS block read from somewhere other than the console, like DumpCurrentFS's
+// output or one of the last 16 blocks of a NAND dump
+#[derive(Debug, Clone)]
+pub struct FsImage(FSBlock);
+
+impl FsImage {
+    pub fn parse<T: AsRef<[u8]>>(data: T) -> Result<Self> {
+        match FSBlock::read(data) {
+            Ok(block) => Ok(Self(block)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn files(&self) -> Files<'_> {
+        self.0.files()
+    }
+
+    pub fn check(&self) -> Vec<FsProblem> {
+        self.0.clone().check(false)
+    }
+
+    pub fn fat_view(&self) -> FatView {
+        self.0.fat_view()
+    }
+
+    pub fn fragmentation_report(&self) -> FragmentationReport {
+        self.0.fragmentation()
+    }
+}
+
+fn block_progress_bar(len: u64) -> ProgressBar {
+    ProgressBar::new(len).with_style(
+        ProgressStyle::with_template(
+            "{wide_bar} {bytes}/{total_bytes}, eta {eta} ({binary_bytes_per_sec})",
+        )
+        .unwrap(),
+    )
+}
+
+// whether a block is somewhere a file can live, between the SKSA and the FS
+fn in_data_area<T: Into<u32>>(block: T) -> bool {
+    (SKSA_BLOCKS..FS_START_BLOCK).contains(&block.into())
+}
+
+// * and ? wildcards, matched against the name and extension separately (so
+// "*.rec" won't match "a.b.rec") and ignoring case. a pattern with no dot is
+// matched against the whole name
+pub(crate) fn matches_pattern(pattern: &str, filename: &str) -> bool {
+    match (pattern.split_once('.'), filename.split_once('.')) {
+        (Some((name_pat, ext_pat)), Some((name, ext))) => {
+            wildcard_match(name_pat.as_bytes(), name.as_bytes())
+                && wildcard_match(ext_pat.as_bytes(), ext.as_bytes())
+        }
+        (Some((name_pat, ext_pat)), None) => {
+            wildcard_match(name_pat.as_bytes(), filename.as_bytes())
+                && wildcard_match(ext_pat.as_bytes(), b"")
+        }
+        (None, _) => wildcard_match(pattern.as_bytes(), filename.as_bytes()),
+    }
+}
+
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // where the last * was, and how much of the name it's swallowed so far
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p].eq_ignore_ascii_case(&name[n])) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
 impl FileEntry {
     fn valid(&self) -> bool {
         self.name[0] != 0 && self.valid == FileValid::Valid && self.start != FATEntry::EndOfChain
     }
 
     fn set_filename(&mut self, filename: &str) -> Result<()> {
-        let split = filename.split('.').collect::<Vec<_>>();
-        let (name, ext) = if split.len() > 1 {
-            (split[0], split[1])
-        } else {
-            (split[0], "")
-        };
-
-        if name.len() > 8 || ext.len() > 3 {
-            return Err(LibBBError::FileNameTooLong(filename.to_string()));
-        }
+        let filename = FatFilename::new(filename)?;
+        let (name, ext) = (filename.name(), filename.ext());
 
         self.name
             .copy_from_slice((name.to_owned() + &"\0".repeat(8 - name.len())).as_bytes());
@@ -171,6 +705,41 @@ impl FileEntry {
         )
     }
 
+    pub fn name(&self) -> String {
+        self.get_fullname()
+    }
+
+    pub fn set_name<T: AsRef<str>>(&mut self, filename: T) -> Result<()> {
+        self.set_filename(filename.as_ref())
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.valid()
+    }
+
+    pub fn set_valid(&mut self, valid: bool) {
+        self.valid = if valid {
+            FileValid::Valid
+        } else {
+            FileValid::Invalid
+        };
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn set_size(&mut self, size: u32) {
+        self.size = size;
+    }
+
+    pub fn start_block(&self) -> Option<u16> {
+        match self.start {
+            FATEntry::Chain(b) => Some(b),
+            _ => None,
+        }
+    }
+
     fn clear(&mut self) {
         self.name = [0; 8];
         self.ext = [0; 3];
@@ -180,11 +749,11 @@ impl FileEntry {
     }
 }
 
-impl BBPlayer {
+impl<B: Transport> BBPlayer<B> {
     fn get_file(&mut self, filename: &str) -> Result<Option<&mut FileEntry>> {
         if let Some(block) = &mut self.current_fs_block {
             for file in &mut block.entries {
-                if file.valid() && file.get_fullname() == filename {
+                if file.valid() && file.get_fullname().eq_ignore_ascii_case(filename) {
                     return Ok(Some(file));
                 }
             }
@@ -194,10 +763,10 @@ impl BBPlayer {
         }
     }
 
-    fn find_file(&self, filename: &str) -> Result<Option<&FileEntry>> {
+    pub(super) fn find_file(&self, filename: &str) -> Result<Option<&FileEntry>> {
         if let Some(block) = &self.current_fs_block {
             for file in &block.entries {
-                if file.valid() && file.get_fullname() == filename {
+                if file.valid() && file.get_fullname().eq_ignore_ascii_case(filename) {
                     return Ok(Some(file));
                 }
             }
@@ -207,6 +776,16 @@ impl BBPlayer {
         }
     }
 
+    fn find_file_index(&self, filename: &str) -> Result<Option<usize>> {
+        if let Some(block) = &self.current_fs_block {
+            Ok(block.entries.iter().position(|file| {
+                file.valid() && file.get_fullname().eq_ignore_ascii_case(filename)
+            }))
+        } else {
+            Err(LibBBError::NoFSBlock)
+        }
+    }
+
     fn rename_file(&mut self, from: &str, to: &str) -> Result<()> {
         match self.get_file(from)? {
             Some(f) => f.set_filename(to),
@@ -257,6 +836,8 @@ impl BBPlayer {
         }
     }
 
+    // each commit goes in the slot below the last one (wrapping round the 16 FS
+    // blocks), so the previous 15 generations stay on the NAND
     fn update_fs(&mut self) -> Result<()> {
         let next_index = (self.current_fs_index.wrapping_sub(1) % 16) + 0xFF0;
 
@@ -267,27 +848,37 @@ impl BBPlayer {
                 Ok(bl) => bl,
                 Err(e) => return Err(e.into()),
             };
-            self.write_block_spare(&block, &self.current_fs_spare, next_index)?;
+            self.write_block_spare(&block, &self.current_fs_spare, next_index, false)?;
+            self.current_fs_index = next_index - 0xFF0;
 
+            if self.dry_run {
+                return Ok(());
+            }
             self.init_fs()
         } else {
             Err(LibBBError::NoFSBlock)
         }
     }
 
+    // a copy with a bad magic or checksum is passed over, so a commit that was
+    // cut short falls back to the generation before it
     fn check_seqno(&mut self, block_num: u32, current_seqno: u32) -> Result<u32> {
         let (block, spare) = self.read_block_spare(block_num)?;
         let seqno = num_from_arr(&block[0x3FF8..0x3FFC]);
-        if seqno > current_seqno {
-            self.current_fs_block = match FSBlock::read(&block) {
-                Ok(b) => Some(b),
-                Err(e) => return Err(e.into()),
-            };
-            self.current_fs_spare = spare;
-            self.current_fs_index = block_num - 0xFF0;
-            Ok(seqno)
-        } else {
-            Ok(current_seqno)
+        if seqno <= current_seqno || !FSBlock::magic_valid(&block) {
+            return Ok(current_seqno);
+        }
+        match FSBlock::read(&block) {
+            Ok(b) => {
+                self.current_fs_block = Some(b);
+                self.current_fs_spare = spare;
+                self.current_fs_index = block_num - 0xFF0;
+                Ok(seqno)
+            }
+            Err(e) => {
+                warn!("skipping FS copy in block {block_num:#X} (seqno {seqno}): {e}");
+                Ok(current_seqno)
+            }
         }
     }
 
@@ -299,108 +890,677 @@ impl BBPlayer {
         Ok(current_seqno != 0)
     }
 
-    pub(super) fn list_file_blocks(&self, filename: &str) -> Result<Option<Vec<u16>>> {
-        if let Some(block) = &self.current_fs_block {
-            let file = match self.find_file(filename)? {
-                Some(f) => f,
-                None => return Ok(None),
-            };
-            let mut rv = vec![];
-            let mut next_block = file.start;
-            while let FATEntry::Chain(b) = next_block {
-                rv.push(b);
-                next_block = block.fat[b as usize];
-            }
-            Ok(Some(rv))
-        } else {
-            Err(LibBBError::NoFSBlock)
-        }
+    // everything that changes the FS comes through here, so this is also where
+    // the cache is brought up to date first. a dry run still edits the cached
+    // FS as it plans; put it back afterwards so it matches the console again
+    pub(super) fn with_fs_rollback<T, F: FnOnce(&mut Self) -> Result<T>>(
+        &mut self,
+        f: F,
+    ) -> Result<T> {
+        self.refresh_if_stale()?;
+        self.with_fs_restored(f)
     }
 
-    pub(super) fn list_files(&self) -> Result<Vec<(String, u32)>> {
-        if let Some(block) = &self.current_fs_block {
-            Ok(block
-                .entries
-                .iter()
-                .filter_map(|e| {
-                    if e.valid() {
-                        Some((e.get_fullname(), e.size))
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<_>>())
-        } else {
-            Err(LibBBError::NoFSBlock)
+    // the dry run half of with_fs_rollback, for changes that don't care what
+    // the FS on the console looks like now
+    pub(super) fn with_fs_restored<T, F: FnOnce(&mut Self) -> Result<T>>(
+        &mut self,
+        f: F,
+    ) -> Result<T> {
+        if !self.dry_run {
+            return f(self);
         }
+        let saved = (
+            self.current_fs_index,
+            self.current_fs_block.clone(),
+            self.current_fs_spare.clone(),
+        );
+        let result = f(self);
+        (
+            self.current_fs_index,
+            self.current_fs_block,
+            self.current_fs_spare,
+        ) = saved;
+        result
     }
 
-    fn free_blocks(&mut self, mut next_block: FATEntry) {
-        if let Some(block) = &mut self.current_fs_block {
-            while let FATEntry::Chain(b) = next_block {
-                let b = b as usize;
-                next_block = block.fat[b];
-                block.fat[b] = FATEntry::Free;
-            }
+    pub(crate) fn refresh_if_stale(&mut self) -> Result<()> {
+        if !self.fs_is_stale()? {
+            return Ok(());
+        }
+
+        let seqno = self.current_fs_block.as_ref().map_or(0, |b| b.footer.seqno);
+        warn!("the FS has changed since it was read (seqno {seqno}); reloading it");
+        if !self.get_current_fs()? {
+            return Err(LibBBError::FS);
         }
+        Ok(())
     }
 
-    pub(crate) fn delete_file(&mut self, filename: &str) -> Result<()> {
-        let file = match self.get_file(filename)? {
-            Some(f) => f,
-            None => return Ok(()),
+    // whether the console has committed an FS we haven't seen, going by the slot
+    // we loaded and the one the next commit would go to
+    pub(crate) fn fs_is_stale(&self) -> Result<bool> {
+        let seqno = match &self.current_fs_block {
+            Some(block) if self.fs_refresh_policy == FsRefreshPolicy::BeforeWrite => {
+                block.footer.seqno
+            }
+            _ => return Ok(false),
         };
-        let start = file.start;
-        file.clear();
+        let current = self.current_fs_index + 0xFF0;
+        let next = (self.current_fs_index.wrapping_sub(1) % 16) + 0xFF0;
 
-        self.free_blocks(start);
-        Ok(())
+        let (block, _) = self.read_block_spare(current)?;
+        if num_from_arr::<u32, _>(&block[0x3FF8..0x3FFC]) != seqno {
+            return Ok(true);
+        }
+        let (block, _) = self.read_block_spare(next)?;
+        Ok(FSBlock::magic_valid(&block) && num_from_arr::<u32, _>(&block[0x3FF8..0x3FFC]) > seqno)
     }
 
-    pub(super) fn delete_file_and_update(&mut self, filename: &str) -> Result<()> {
-        self.delete_file(filename)?;
-        self.update_fs()
+    // after a full restore both our cached FS and the console's are stale; make
+    // sure the image brought a valid FS with it, then have the console reload it
+    pub(super) fn reload_restored_fs(&mut self) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        if !self.get_current_fs()? {
+            return Err(LibBBError::FS);
+        }
+        self.init_fs()
     }
 
-    pub(super) fn get_stats(&self) -> Result<(usize, usize, usize, u32)> {
-        if let Some(block) = &self.current_fs_block {
-            let (free, used, bad) = block.fat.iter().fold((0, 0, 0), |(a, b, c), e| match e {
-                FATEntry::Free => (a + 1, b, c),
-                FATEntry::BadBlock => (a, b, c + 1),
-                _ => (a, b + 1, c),
+    pub(super) fn list_fs_copies(&self) -> Result<Vec<FsCopy>> {
+        let mut rv = vec![];
+        for i in (0xFF0..=0xFFF).rev() {
+            let (block, _) = self.read_block_spare(i)?;
+            rv.push(FsCopy {
+                block: i,
+                seqno: num_from_arr(&block[0x3FF8..0x3FFC]),
+                magic_valid: FSBlock::magic_valid(&block),
+                checksum_valid: FSBlock::checksum_valid(&block),
+                current: self.current_fs_block.is_some() && i == self.current_fs_index + 0xFF0,
             });
-            Ok((free, used, bad, block.footer.seqno))
-        } else {
-            Err(LibBBError::NoFSBlock)
         }
+        Ok(rv)
     }
 
-    fn read_blocks(&self, file: &FileEntry) -> Result<Option<Vec<u8>>> {
-        if let Some(block) = &self.current_fs_block {
-            let mut filebuf = Vec::with_capacity(file.size as usize);
-            let mut next_block = file.start;
-            let bar = ProgressBar::new(file.size.into()).with_style(
-                ProgressStyle::with_template(
-                    "{wide_bar} {bytes}/{total_bytes}, eta {eta} ({binary_bytes_per_sec})",
-                )
-                .unwrap(),
-            );
-            while filebuf.len() < file.size as usize && let FATEntry::Chain(b) = next_block {
-                let (read_block, _) = self.read_block_spare(b.into())?;
-                let to_write = &read_block[..read_block.len().min(file.size as usize - filebuf.len())];
-                bar.inc(to_write.len() as u64);
-                filebuf.extend(to_write);
-                next_block = block.fat[b as usize];
+    // older FS copies are read straight off the NAND; a deleted file is only
+    // offered if none of its blocks have been reused since, and only its most
+    // recent version is. names that exist now are left out
+    pub(super) fn list_recoverable(&self) -> Result<Vec<RecoverableFile>> {
+        let current = match &self.current_fs_block {
+            Some(b) => b,
+            None => return Err(LibBBError::NoFSBlock),
+        };
+
+        let mut generations = vec![];
+        for i in 0xFF0..=0xFFF {
+            let (block, _) = self.read_block_spare(i)?;
+            if let Ok(fs) = FSBlock::read(&block)
+                && fs.footer.seqno < current.footer.seqno
+            {
+                generations.push((i, fs));
             }
-            Ok(Some(filebuf))
-        } else {
-            Err(LibBBError::NoFSBlock)
         }
-    }
-
-    pub(super) fn read_file(&self, filename: &str) -> Result<Option<Vec<u8>>> {
-        let file = match self.find_file(filename)? {
-            Some(f) => f,
+        generations.sort_by_key(|(_, fs)| std::cmp::Reverse(fs.footer.seqno));
+
+        let mut rv: Vec<RecoverableFile> = vec![];
+        for (block, fs) in &generations {
+            for entry in fs.entries.iter().filter(|e| e.valid()) {
+                let name = entry.get_fullname();
+                if self.find_file(&name)?.is_some() || rv.iter().any(|f| f.name == name) {
+                    continue;
+                }
+                let Some(blocks) = fs.chain_of(entry) else {
+                    continue;
+                };
+                if !blocks
+                    .iter()
+                    .all(|&b| current.fat[b as usize] == FATEntry::Free)
+                {
+                    continue;
+                }
+                rv.push(RecoverableFile {
+                    name,
+                    size: entry.size,
+                    blocks,
+                    source: RecoverySource::Generation {
+                        block: *block,
+                        seqno: fs.footer.seqno,
+                    },
+                });
+            }
+        }
+
+        for blocks in current.orphan_chains() {
+            rv.push(RecoverableFile {
+                name: format!("lost{:04x}.chk", blocks[0]),
+                size: (blocks.len() * BLOCK_SIZE) as u32,
+                blocks,
+                source: RecoverySource::OrphanChain,
+            });
+        }
+        Ok(rv)
+    }
+
+    pub(super) fn undelete_file(&mut self, filename: &str) -> Result<()> {
+        if self.find_file(filename)?.is_some() {
+            return Err(LibBBError::FileAlreadyExists(filename.to_string()));
+        }
+        let file = match self
+            .list_recoverable()?
+            .into_iter()
+            .find(|f| f.name.eq_ignore_ascii_case(filename))
+        {
+            Some(f) => f,
+            None => return Err(LibBBError::FileNotFound(filename.to_string())),
+        };
+
+        // a deleted file's blocks have to be free still, and an orphan's still
+        // unclaimed by any file. either way the chain is linked up afresh, since
+        // an orphan's last link may have been left pointing into another file
+        let expected = match file.source {
+            RecoverySource::Generation { .. } => BlockState::Free,
+            RecoverySource::OrphanChain => BlockState::Lost,
+        };
+        let view = self.view_fat()?;
+        if !file
+            .blocks
+            .iter()
+            .all(|&b| view.blocks.get(b as usize) == Some(&expected))
+        {
+            return Err(LibBBError::BlocksReused(filename.to_string()));
+        }
+        self.link_chain(&file.blocks)?;
+        self.write_file_entry(filename, file.blocks[0].into(), file.size)?;
+        self.update_fs()
+    }
+
+    // the copies that look like an FS at all, newest first; get_current_fs
+    // loads the first of these whose checksum is valid
+    pub(super) fn list_fs_generations(&self) -> Result<Vec<FsCopy>> {
+        let mut rv = self
+            .list_fs_copies()?
+            .into_iter()
+            .filter(|copy| copy.magic_valid)
+            .collect::<Vec<_>>();
+        rv.sort_by_key(|copy| std::cmp::Reverse(copy.seqno));
+        Ok(rv)
+    }
+
+    // commits the copy in block_num again with a seqno above every other copy's.
+    // blocks freed since it was current may have been reused, so its files
+    // are worth checking (or ReadFile-ing) afterwards
+    pub(super) fn rollback_fs(&mut self, block_num: u32) -> Result<()> {
+        if !(FS_START_BLOCK..FS_START_BLOCK + 16).contains(&block_num) {
+            return Err(LibBBError::NotAnFSCopy(block_num));
+        }
+        // committing a copy gives it a fresh checksum, so a corrupt one would
+        // come back looking valid
+        let copies = self.list_fs_copies()?;
+        if !copies
+            .iter()
+            .any(|copy| copy.block == block_num && copy.magic_valid && copy.checksum_valid)
+        {
+            return Err(LibBBError::CorruptFSCopy(block_num));
+        }
+        let newest = copies
+            .iter()
+            .filter(|copy| copy.magic_valid)
+            .map(|copy| copy.seqno)
+            .max()
+            .unwrap_or(0);
+
+        let (block, spare) = self.read_block_spare(block_num)?;
+        let mut fs = FSBlock::read(&block)?;
+        fs.footer.seqno = newest;
+        self.current_fs_block = Some(fs);
+        self.current_fs_spare = spare;
+        self.update_fs()
+    }
+
+    pub(super) fn list_file_blocks(&self, filename: &str) -> Result<Option<Vec<u16>>> {
+        if let Some(block) = &self.current_fs_block {
+            let file = match self.find_file(filename)? {
+                Some(f) => f,
+                None => return Ok(None),
+            };
+            let mut rv = vec![];
+            let mut next_block = file.start;
+            while let FATEntry::Chain(b) = next_block {
+                rv.push(b);
+                next_block = block.fat[b as usize];
+            }
+            Ok(Some(rv))
+        } else {
+            Err(LibBBError::NoFSBlock)
+        }
+    }
+
+    pub(crate) fn next_free_block_after(&self, prev: Option<u16>) -> Result<u16> {
+        let start_at = prev.map_or(SKSA_BLOCKS as usize, |b| b as usize + 1);
+        Ok(self.find_next_free_block(start_at)? as u16)
+    }
+
+    // the blocks are already on the NAND; this links them up as temp.tmp, checks
+    // the console agrees on the checksum, then swaps it in for any existing file
+    pub(crate) fn commit_streamed_file(
+        &mut self,
+        filename: &str,
+        blocks: &[u16],
+        chksum: u32,
+    ) -> Result<()> {
+        self.commit_file_blocks(filename, blocks, (blocks.len() * BLOCK_SIZE) as u32, chksum)
+    }
+
+    fn commit_file_blocks(
+        &mut self,
+        filename: &str,
+        blocks: &[u16],
+        size: u32,
+        chksum: u32,
+    ) -> Result<()> {
+        self.write_file_entry("temp.tmp", blocks[0].into(), size)?;
+        self.link_chain(blocks)?;
+        self.update_fs()?;
+
+        if !self.dry_run && !self.file_checksum_cmp("temp.tmp", chksum, size)? {
+            return Err(LibBBError::ChecksumFailed(filename.to_string(), chksum));
+        }
+        self.delete_file(filename)?;
+        self.rename_file("temp.tmp", filename)?;
+        self.update_fs()
+    }
+
+    // each block goes straight back out to a free one as it's read, so only one
+    // is ever held on the host
+    pub(super) fn copy_file(&mut self, from: &str, to: &str) -> Result<()> {
+        let size = match self.find_file(from)? {
+            Some(file) => file.size as usize,
+            None => return Err(LibBBError::FileNotFound(from.to_string())),
+        };
+        if self.find_file(to)?.is_some() {
+            return Err(LibBBError::FileAlreadyExists(to.to_string()));
+        }
+        let source = self.list_file_blocks(from)?.unwrap_or_default();
+        let required_blocks = Self::bytes_to_blocks(size).max(1);
+        if source.len() < required_blocks {
+            return Err(LibBBError::FileSizeExceedsChain(
+                from.to_string(),
+                size as u32,
+                source.len(),
+            ));
+        }
+        self.check_space(required_blocks, 0)?;
+
+        let bar = block_progress_bar((required_blocks * BLOCK_SIZE) as u64);
+        let bulk = self.start_bulk("CopyFile", required_blocks as u32);
+        let mut blocks: Vec<u16> = Vec::with_capacity(required_blocks);
+        let mut chksum = 0u32;
+        for (index, &b) in source.iter().take(required_blocks).enumerate() {
+            let (block, _) = self.read_block_spare(b.into())?;
+            let in_file = size.saturating_sub(index * BLOCK_SIZE).min(BLOCK_SIZE);
+            chksum = block[..in_file]
+                .iter()
+                .fold(chksum, |a, &e| a.wrapping_add(e as u32));
+
+            let next = self.next_free_block_after(blocks.last().copied())?;
+            self.write_block_spare(&block, &BLANK_SPARE, next.into(), false)?;
+            blocks.push(next);
+            bar.inc(BLOCK_SIZE as u64);
+            bulk.advance();
+        }
+        bulk.finish();
+
+        self.commit_file_blocks(to, &blocks, size as u32, chksum)
+    }
+
+    // the progress only carries over if it's for the same data and the cached
+    // FS still has all its blocks free; otherwise the upload starts over
+    pub(super) fn resume_write(
+        &mut self,
+        filename: &str,
+        data: &[u8],
+        progress: &mut WriteProgress,
+    ) -> Result<()> {
+        let chksum = Self::calculate_file_checksum(data);
+        let required_blocks = Self::bytes_to_blocks(data.len()).max(1);
+
+        if progress.chksum != chksum
+            || progress.blocks.len() != required_blocks
+            || progress.written > required_blocks
+            || !self.blocks_free(&progress.blocks)?
+        {
+            self.check_space(required_blocks, 0)?;
+            let mut blocks = Vec::with_capacity(required_blocks);
+            for _ in 0..required_blocks {
+                blocks.push(self.next_free_block_after(blocks.last().copied())?);
+            }
+            *progress = WriteProgress {
+                blocks,
+                written: 0,
+                chksum,
+            };
+        }
+
+        let bar = block_progress_bar((required_blocks * BLOCK_SIZE) as u64);
+        bar.inc((progress.written * BLOCK_SIZE) as u64);
+
+        let bulk = self.start_bulk("ResumeWrite", (required_blocks - progress.written) as u32);
+        for index in progress.written..required_blocks {
+            let start = (index * BLOCK_SIZE).min(data.len());
+            let end = (start + BLOCK_SIZE).min(data.len());
+            let mut block = data[start..end].to_vec();
+            block.resize(BLOCK_SIZE, 0);
+            self.write_block_spare(&block, &BLANK_SPARE, progress.blocks[index].into(), false)?;
+            progress.written = index + 1;
+            bar.inc(BLOCK_SIZE as u64);
+            bulk.advance();
+        }
+        bulk.finish();
+
+        let blocks = progress.blocks.clone();
+        self.commit_streamed_file(filename, &blocks, chksum)?;
+        *progress = WriteProgress::default();
+        Ok(())
+    }
+
+    fn blocks_free(&self, blocks: &[u16]) -> Result<bool> {
+        if let Some(block) = &self.current_fs_block {
+            Ok(blocks
+                .iter()
+                .all(|&b| in_data_area(b) && matches!(block.fat[b as usize], FATEntry::Free)))
+        } else {
+            Err(LibBBError::NoFSBlock)
+        }
+    }
+
+    fn link_chain(&mut self, blocks: &[u16]) -> Result<()> {
+        if let Some(block) = &mut self.current_fs_block {
+            for pair in blocks.windows(2) {
+                block.fat[pair[0] as usize] = FATEntry::Chain(pair[1]);
+            }
+            block.fat[blocks[blocks.len() - 1] as usize] = FATEntry::EndOfChain;
+            Ok(())
+        } else {
+            Err(LibBBError::NoFSBlock)
+        }
+    }
+
+    pub(super) fn file_chain(&self, filename: &str) -> Result<Option<Vec<ChainLink>>> {
+        let blocks = match self.list_file_blocks(filename)? {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        let mut prev: Option<u16> = None;
+        Ok(Some(
+            blocks
+                .into_iter()
+                .map(|block| {
+                    let contiguous = match prev {
+                        Some(p) => p.wrapping_add(1) == block,
+                        None => true,
+                    };
+                    prev = Some(block);
+                    ChainLink { block, contiguous }
+                })
+                .collect(),
+        ))
+    }
+
+    pub(super) fn list_files(&self) -> Result<Vec<(String, u32)>> {
+        if let Some(block) = &self.current_fs_block {
+            Ok(block
+                .entries
+                .iter()
+                .filter_map(|e| {
+                    if e.valid() {
+                        Some((e.get_fullname(), e.size))
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>())
+        } else {
+            Err(LibBBError::NoFSBlock)
+        }
+    }
+
+    pub(super) fn list_entries(&self) -> Result<Vec<FileInfo>> {
+        Ok(self.cached_files()?.collect())
+    }
+
+    pub(super) fn stat_file(&self, filename: &str) -> Result<Option<FileInfo>> {
+        match (&self.current_fs_block, self.find_file(filename)?) {
+            (Some(block), Some(file)) => Ok(Some(block.file_info(file))),
+            _ => Ok(None),
+        }
+    }
+
+    pub(super) fn view_fat(&self) -> Result<FatView> {
+        match &self.current_fs_block {
+            Some(block) => Ok(block.fat_view()),
+            None => Err(LibBBError::NoFSBlock),
+        }
+    }
+
+    pub(super) fn fragmentation_report(&self) -> Result<FragmentationReport> {
+        match &self.current_fs_block {
+            Some(block) => Ok(block.fragmentation()),
+            None => Err(LibBBError::NoFSBlock),
+        }
+    }
+
+    pub(super) fn cached_files(&self) -> Result<Files<'_>> {
+        match &self.current_fs_block {
+            Some(block) => Ok(block.files()),
+            None => Err(LibBBError::NoFSBlock),
+        }
+    }
+
+    pub(super) fn list_matching(&self, pattern: &str) -> Result<Vec<FileInfo>> {
+        Ok(self
+            .cached_files()?
+            .filter(|file| matches_pattern(pattern, &file.name))
+            .collect())
+    }
+
+    pub(super) fn read_matching(&self, pattern: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        let filenames = self
+            .list_matching(pattern)?
+            .into_iter()
+            .map(|file| file.name)
+            .collect::<Vec<_>>();
+        let files = self.read_files(&filenames.iter().map(String::as_str).collect::<Vec<_>>())?;
+        Ok(filenames
+            .into_iter()
+            .zip(files)
+            .filter_map(|(name, data)| data.map(|d| (name, d)))
+            .collect())
+    }
+
+    // all the matches go in one FS commit; returns the names that were deleted
+    pub(super) fn delete_matching(&mut self, pattern: &str) -> Result<Vec<String>> {
+        let filenames = self
+            .list_matching(pattern)?
+            .into_iter()
+            .map(|file| file.name)
+            .collect::<Vec<_>>();
+        if filenames.is_empty() {
+            return Ok(filenames);
+        }
+        for filename in &filenames {
+            self.delete_file(filename)?;
+        }
+        self.update_fs()?;
+        Ok(filenames)
+    }
+
+    fn free_blocks(&mut self, mut next_block: FATEntry) {
+        if let Some(block) = &mut self.current_fs_block {
+            while let FATEntry::Chain(b) = next_block {
+                let b = b as usize;
+                next_block = block.fat[b];
+                block.fat[b] = FATEntry::Free;
+            }
+        }
+    }
+
+    pub(crate) fn delete_file(&mut self, filename: &str) -> Result<()> {
+        let file = match self.get_file(filename)? {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+        let start = file.start;
+        file.clear();
+
+        self.free_blocks(start);
+        Ok(())
+    }
+
+    // older FS copies still point at the blocks, so ListRecoverable can still
+    // offer the file afterwards, but only as zeroes
+    pub(super) fn shred_file(&mut self, filename: &str) -> Result<()> {
+        let index = match self.find_file_index(filename)? {
+            Some(i) => i,
+            None => return Err(LibBBError::FileNotFound(filename.to_string())),
+        };
+        // a broken chain could lead anywhere, including the system area, so it
+        // has to check out before anything's overwritten
+        self.check_file_entry(index)?;
+        let blocks = self.list_file_blocks(filename)?.unwrap_or_default();
+
+        let zeroes = vec![0x00; BLOCK_SIZE];
+        let bulk = self.start_bulk("ShredFile", blocks.len() as u32);
+        for b in blocks {
+            self.write_block_spare(&zeroes, &BLANK_SPARE, b.into(), false)?;
+            bulk.advance();
+        }
+        bulk.finish();
+
+        self.delete_file_and_update(filename)
+    }
+
+    // every block the FAT has as free gets fill written over it; bad and
+    // allocated blocks, and the system area, are left alone. the FS itself
+    // doesn't change, so a cancelled wipe leaves nothing to clean up
+    pub(super) fn wipe_free_space(&self, fill: u8, cancel: &AtomicBool) -> Result<u32> {
+        let free = match &self.current_fs_block {
+            Some(block) => (SKSA_BLOCKS..FS_START_BLOCK)
+                .filter(|&b| block.fat[b as usize] == FATEntry::Free)
+                .collect::<Vec<_>>(),
+            None => return Err(LibBBError::NoFSBlock),
+        };
+
+        let data = vec![fill; BLOCK_SIZE];
+        let bar = block_progress_bar((free.len() * BLOCK_SIZE) as u64);
+        let bulk = self.start_bulk("WipeFreeSpace", free.len() as u32);
+        for (done, &b) in free.iter().enumerate() {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(LibBBError::WipeCancelled(done as u32));
+            }
+            self.write_block_spare(&data, &BLANK_SPARE, b, false)?;
+            bar.inc(BLOCK_SIZE as u64);
+            bulk.advance();
+        }
+        bulk.finish();
+
+        Ok(free.len() as u32)
+    }
+
+    pub(super) fn delete_file_and_update(&mut self, filename: &str) -> Result<()> {
+        self.delete_file(filename)?;
+        self.update_fs()
+    }
+
+    // only the directory entry changes, so none of the file's blocks are touched
+    pub(super) fn rename_file_and_update(&mut self, from: &str, to: &str) -> Result<()> {
+        if from == to {
+            return Ok(());
+        }
+        if self.find_file(to)?.is_some() {
+            return Err(LibBBError::FileAlreadyExists(to.to_string()));
+        }
+        self.rename_file(from, to)?;
+        self.update_fs()
+    }
+
+    pub(super) fn get_stats(&self) -> Result<FsStats> {
+        if let Some(block) = &self.current_fs_block {
+            let (free, used, bad) = block.fat.iter().fold((0, 0, 0), |(a, b, c), e| match e {
+                FATEntry::Free => (a + 1, b, c),
+                FATEntry::BadBlock => (a, b, c + 1),
+                _ => (a, b + 1, c),
+            });
+            Ok(FsStats {
+                free_blocks: free,
+                used_blocks: used,
+                bad_blocks: bad,
+                seqno: block.footer.seqno,
+                total_bytes_free: free * BLOCK_SIZE,
+                total_bytes_used: used * BLOCK_SIZE,
+            })
+        } else {
+            Err(LibBBError::NoFSBlock)
+        }
+    }
+
+    fn read_blocks(&self, file: &FileEntry) -> Result<Option<Vec<u8>>> {
+        if let Some(block) = &self.current_fs_block {
+            let mut filebuf = Vec::with_capacity(file.size as usize);
+            let mut next_block = file.start;
+            let bar = block_progress_bar(file.size.into());
+            while filebuf.len() < file.size as usize && let FATEntry::Chain(b) = next_block {
+                let (read_block, _) = self.read_block_spare(b.into())?;
+                let to_write = &read_block[..read_block.len().min(file.size as usize - filebuf.len())];
+                bar.inc(to_write.len() as u64);
+                filebuf.extend(to_write);
+                next_block = block.fat[b as usize];
+            }
+            Ok(Some(filebuf))
+        } else {
+            Err(LibBBError::NoFSBlock)
+        }
+    }
+
+    // follows the FAT chain past the blocks before offset without reading them,
+    // and stops once len bytes (or the end of the file) have been read
+    pub(super) fn read_file_range(
+        &self,
+        filename: &str,
+        offset: u32,
+        len: u32,
+    ) -> Result<Option<Vec<u8>>> {
+        let file = match self.find_file(filename)? {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        let Some(block) = &self.current_fs_block else {
+            return Err(LibBBError::NoFSBlock);
+        };
+
+        let start = offset.min(file.size) as usize;
+        let end = offset.saturating_add(len).min(file.size) as usize;
+        let mut buf = Vec::with_capacity(end - start);
+
+        let mut next_block = file.start;
+        let mut block_start = 0;
+        while block_start < end && let FATEntry::Chain(b) = next_block {
+            if block_start + BLOCK_SIZE > start {
+                let (read_block, _) = self.read_block_spare(b.into())?;
+                let from = start.saturating_sub(block_start);
+                let to = (end - block_start).min(BLOCK_SIZE);
+                buf.extend(&read_block[from..to]);
+            }
+            block_start += BLOCK_SIZE;
+            next_block = block.fat[b as usize];
+        }
+        Ok(Some(buf))
+    }
+
+    pub(super) fn read_file(&self, filename: &str) -> Result<Option<Vec<u8>>> {
+        let file = match self.find_file(filename)? {
+            Some(f) => f,
             None => return Ok(None),
         };
         self.read_blocks(file)
@@ -426,11 +1586,25 @@ impl BBPlayer {
                     Ok(false)
                 } else {
                     let block_count = self.get_file_block_count(filename)?;
+                    self.check_space(required_blocks, block_count)?;
                     self.delete_file(filename)?;
-                    Ok(required_blocks < self.get_free_block_count()? + block_count)
+                    Ok(true)
                 }
             }
-            None => Ok(required_blocks <= self.get_free_block_count()?),
+            None => {
+                self.check_space(required_blocks, 0)?;
+                Ok(true)
+            }
+        }
+    }
+
+    // reclaimable is whatever the write will free up before it needs the space
+    fn check_space(&self, required_blocks: usize, reclaimable: usize) -> Result<()> {
+        let available = self.get_free_block_count()? + reclaimable;
+        if required_blocks > available {
+            Err(LibBBError::InsufficientSpace(required_blocks, available))
+        } else {
+            Ok(())
         }
     }
 
@@ -440,10 +1614,8 @@ impl BBPlayer {
         blocks_to_write: &[u16],
         required_blocks: usize,
     ) -> Result<()> {
-        const BLANK_SPARE: [u8; SPARE_SIZE] = [0xFF; SPARE_SIZE];
-
         assert!(
-            blocks_to_write.iter().all(|e| (0x40..0xFF0).contains(e)),
+            blocks_to_write.iter().all(|&e| in_data_area(e)),
             "Trying to write to SKSA or FAT area!"
         );
 
@@ -457,19 +1629,17 @@ impl BBPlayer {
             ));
         }
 
-        let bar = ProgressBar::new((blocks_to_write.len() * BLOCK_SIZE) as u64).with_style(
-            ProgressStyle::with_template(
-                "{wide_bar} {bytes}/{total_bytes}, eta {eta} ({binary_bytes_per_sec})",
-            )
-            .unwrap(),
-        );
+        let bar = block_progress_bar((blocks_to_write.len() * BLOCK_SIZE) as u64);
 
+        let bulk = self.start_bulk("WriteFile", blocks_to_write.len() as u32);
         for (block, &index) in chunks.zip(blocks_to_write) {
             let mut block = block.to_vec();
             block.extend(vec![0x00; BLOCK_SIZE - block.len()]);
-            self.write_block_spare(&block, &BLANK_SPARE, index.into())?;
+            self.write_block_spare(&block, &BLANK_SPARE, index.into(), false)?;
             bar.inc(BLOCK_SIZE as u64);
+            bulk.advance();
         }
+        bulk.finish();
 
         Ok(())
     }
@@ -520,12 +1690,7 @@ impl BBPlayer {
         free_blocks.push(start_block as u16);
         let mut prev = start_block as u16;
 
-        let bar = ProgressBar::new(required_blocks as u64).with_style(
-            ProgressStyle::with_template(
-                "{wide_bar} {bytes}/{total_bytes}, eta {eta} ({binary_bytes_per_sec})",
-            )
-            .unwrap(),
-        );
+        let bar = block_progress_bar(required_blocks as u64);
 
         for _ in 0..required_blocks - 1 {
             let next = self.find_next_free_block(prev as usize + 1)? as u16;
@@ -552,7 +1717,7 @@ impl BBPlayer {
     }
 
     fn write_blocks_to_temp_file(&mut self, data: &[u8], required_blocks: usize) -> Result<()> {
-        let start_block = self.find_next_free_block(0x40)?;
+        let start_block = self.find_next_free_block(SKSA_BLOCKS as usize)?;
         self.write_file_entry(
             "temp.tmp",
             start_block,
@@ -569,7 +1734,10 @@ impl BBPlayer {
         chksum: u32,
         required_blocks: usize,
     ) -> Result<()> {
-        if self.file_checksum_cmp("temp.tmp", chksum, (required_blocks * BLOCK_SIZE) as u32)? {
+        // nothing was written on a dry run, so there's nothing to check
+        if self.dry_run
+            || self.file_checksum_cmp("temp.tmp", chksum, (required_blocks * BLOCK_SIZE) as u32)?
+        {
             self.rename_file("temp.tmp", filename)
         } else {
             Err(LibBBError::ChecksumFailed(filename.to_string(), chksum))
@@ -580,6 +1748,10 @@ impl BBPlayer {
         let chksum = Self::calculate_file_checksum(data);
         let required_blocks = Self::bytes_to_blocks(data.len());
 
+        if self.atomic_writes {
+            return self.write_file_atomic(data, filename, chksum, required_blocks);
+        }
+
         if !self.validate_file_write(filename, chksum, required_blocks)? {
             return Ok(());
         };
@@ -592,4 +1764,668 @@ impl BBPlayer {
 
         Ok(())
     }
+
+    // the new blocks all come from free space, and the console's FS keeps
+    // pointing at the old file until commit_streamed_file swaps the entries
+    fn write_file_atomic(
+        &mut self,
+        data: &[u8],
+        filename: &str,
+        chksum: u32,
+        required_blocks: usize,
+    ) -> Result<()> {
+        if self.find_file(filename)?.is_some()
+            && self.file_checksum_cmp(filename, chksum, (required_blocks * BLOCK_SIZE) as u32)?
+        {
+            return Ok(());
+        }
+        self.resume_write(filename, data, &mut WriteProgress::default())
+    }
+
+    // every file's blocks are written before the FS is committed once at the
+    // end, so the console can only check the checksums afterwards. files that
+    // are already there with the same contents are left alone
+    pub(super) fn write_files(&mut self, files: &[(&str, &[u8])]) -> Result<()> {
+        for (index, &(filename, _)) in files.iter().enumerate() {
+            if files[..index]
+                .iter()
+                .any(|(other, _)| other.eq_ignore_ascii_case(filename))
+            {
+                return Err(LibBBError::DuplicateFileName(filename.to_string()));
+            }
+        }
+
+        let mut pending = Vec::with_capacity(files.len());
+        let mut required_blocks = 0;
+        let mut reclaimable = 0;
+        for &(filename, data) in files {
+            let chksum = Self::calculate_file_checksum(data);
+            let blocks = Self::bytes_to_blocks(data.len()).max(1);
+            if self.find_file(filename)?.is_some() {
+                if self.file_checksum_cmp(filename, chksum, (blocks * BLOCK_SIZE) as u32)? {
+                    continue;
+                }
+                if !self.atomic_writes {
+                    reclaimable += self.get_file_block_count(filename)?;
+                }
+            }
+            required_blocks += blocks;
+            pending.push((filename, data, chksum, blocks));
+        }
+        self.check_space(required_blocks, reclaimable)?;
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        // see set_atomic_writes
+        if !self.atomic_writes {
+            for &(filename, ..) in &pending {
+                self.delete_file(filename)?;
+            }
+        }
+
+        let mut chains = Vec::with_capacity(pending.len());
+        for &(.., blocks) in &pending {
+            let mut chain: Vec<u16> = Vec::with_capacity(blocks);
+            for _ in 0..blocks {
+                chain.push(self.next_free_block_after(chain.last().copied())?);
+            }
+            self.link_chain(&chain)?;
+            chains.push(chain);
+        }
+
+        let bar = block_progress_bar((required_blocks * BLOCK_SIZE) as u64);
+        let bulk = self.start_bulk("WriteFiles", required_blocks as u32);
+        for (&(_, data, ..), chain) in pending.iter().zip(&chains) {
+            for (index, &block_num) in chain.iter().enumerate() {
+                let start = (index * BLOCK_SIZE).min(data.len());
+                let end = (start + BLOCK_SIZE).min(data.len());
+                let mut block = data[start..end].to_vec();
+                block.resize(BLOCK_SIZE, 0);
+                self.write_block_spare(&block, &BLANK_SPARE, block_num.into(), false)?;
+                bar.inc(BLOCK_SIZE as u64);
+                bulk.advance();
+            }
+        }
+        bulk.finish();
+
+        // like commit_file_blocks, every file goes in under a temporary name
+        // first and only replaces the real one once the console has checked it
+        let mut temp_names = Vec::with_capacity(pending.len());
+        for (&(.., blocks), chain) in pending.iter().zip(&chains) {
+            let temp_name = self.unused_temp_name(temp_names.len())?;
+            self.write_file_entry(&temp_name, chain[0].into(), (blocks * BLOCK_SIZE) as u32)?;
+            temp_names.push(temp_name);
+        }
+        self.update_fs()?;
+
+        if !self.dry_run {
+            for (&(filename, _, chksum, blocks), temp_name) in pending.iter().zip(&temp_names) {
+                if !self.file_checksum_cmp(temp_name, chksum, (blocks * BLOCK_SIZE) as u32)? {
+                    for temp_name in &temp_names {
+                        self.delete_file(temp_name)?;
+                    }
+                    self.update_fs()?;
+                    return Err(LibBBError::ChecksumFailed(filename.to_string(), chksum));
+                }
+            }
+        }
+
+        for (&(filename, ..), temp_name) in pending.iter().zip(&temp_names) {
+            self.delete_file(filename)?;
+            self.rename_file(temp_name, filename)?;
+        }
+        self.update_fs()
+    }
+
+    // temp.tmp is left for the single file writes, so a batch gets numbered
+    // names, starting the search at `from`. Init clears out any of these a
+    // failed write left behind, along with temp.tmp
+    fn unused_temp_name(&self, from: usize) -> Result<String> {
+        for i in from..10000 {
+            let name = format!("temp{i:04}.tmp");
+            if self.find_file(&name)?.is_none() {
+                return Ok(name);
+            }
+        }
+        Err(LibBBError::NoEmptyFileSlots)
+    }
+
+    pub(super) fn delete_temp_files(&mut self) -> Result<()> {
+        let is_temp = |name: &str| {
+            name == "temp.tmp"
+                || name
+                    .strip_prefix("temp")
+                    .and_then(|n| n.strip_suffix(".tmp"))
+                    .is_some_and(|n| n.len() == 4 && n.bytes().all(|c| c.is_ascii_digit()))
+        };
+        for file in self.list_entries()? {
+            if is_temp(&file.name.to_ascii_lowercase()) {
+                self.delete_file(&file.name)?;
+            }
+        }
+        self.update_fs()
+    }
+
+    // missing files come back as None, in the same place as their name
+    pub(super) fn read_files(&self, filenames: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut files = Vec::with_capacity(filenames.len());
+        for &filename in filenames {
+            let size = self.find_file(filename)?.map(|f| f.size as usize);
+            files.push(size.zip(self.list_file_blocks(filename)?));
+        }
+
+        let total = files.iter().flatten().map(|(size, _)| size).sum::<usize>();
+        let bar = block_progress_bar(total as u64);
+        let bulk = self.start_bulk(
+            "ReadFiles",
+            files
+                .iter()
+                .flatten()
+                .map(|(size, _)| Self::bytes_to_blocks(*size) as u32)
+                .sum(),
+        );
+
+        let mut rv = Vec::with_capacity(files.len());
+        for file in files {
+            let Some((size, blocks)) = file else {
+                rv.push(None);
+                continue;
+            };
+            let mut filebuf = Vec::with_capacity(size);
+            for b in blocks {
+                if filebuf.len() >= size {
+                    break;
+                }
+                let (read_block, _) = self.read_block_spare(b.into())?;
+                let to_write = &read_block[..read_block.len().min(size - filebuf.len())];
+                bar.inc(to_write.len() as u64);
+                bulk.advance();
+                filebuf.extend(to_write);
+            }
+            rv.push(Some(filebuf));
+        }
+        bulk.finish();
+
+        Ok(rv)
+    }
+
+    fn plan_defrag_moves(
+        &mut self,
+        fragmented: Vec<(String, Vec<u16>)>,
+        report: &mut DefragReport,
+    ) -> Result<Vec<DefragMove>> {
+        let mut moves = vec![];
+        for (filename, chain) in fragmented {
+            let (run_start, run_len) = self.largest_free_run()?;
+            if run_len < chain.len() {
+                report.skipped.push(filename);
+                continue;
+            }
+            let blocks_to_write = self.update_fs_links(run_start, chain.len())?;
+            moves.push((filename, chain, blocks_to_write));
+        }
+        Ok(moves)
+    }
+
+    // write the new copies before touching the old chains, so the FS on the
+    // console stays valid until the final commit
+    fn copy_defrag_moves(&self, moves: &[DefragMove]) -> Result<()> {
+        let total = moves.iter().map(|(_, chain, _)| chain.len()).sum::<usize>();
+        let bar = block_progress_bar((total * BLOCK_SIZE) as u64);
+        let bulk = self.start_bulk("Defragment", total as u32);
+        for (_, chain, blocks_to_write) in moves {
+            for (&from, &to) in chain.iter().zip(blocks_to_write) {
+                let (block, _) = self.read_block_spare(from.into())?;
+                self.write_block_spare(&block, &BLANK_SPARE, to.into(), false)?;
+                bar.inc(BLOCK_SIZE as u64);
+                bulk.advance();
+            }
+        }
+        bulk.finish();
+        Ok(())
+    }
+
+    fn largest_free_run(&self) -> Result<(usize, usize)> {
+        if let Some(block) = &self.current_fs_block {
+            let mut largest = (SKSA_BLOCKS as usize, 0);
+            let mut run_start = SKSA_BLOCKS as usize;
+            for (index, entry) in block
+                .fat
+                .iter()
+                .enumerate()
+                .take(FS_START_BLOCK as usize)
+                .skip(SKSA_BLOCKS as usize)
+            {
+                if matches!(entry, FATEntry::Free) {
+                    if index + 1 - run_start > largest.1 {
+                        largest = (run_start, index + 1 - run_start);
+                    }
+                } else {
+                    run_start = index + 1;
+                }
+            }
+            Ok(largest)
+        } else {
+            Err(LibBBError::NoFSBlock)
+        }
+    }
+
+    // the repair is done on a copy, and only committed if there was anything
+    // to fix
+    pub(super) fn check_fs(&mut self, repair: bool) -> Result<FsckReport> {
+        let mut block = match &self.current_fs_block {
+            Some(b) => b.clone(),
+            None => return Err(LibBBError::NoFSBlock),
+        };
+        let problems = block.check(repair);
+        if !repair || problems.is_empty() {
+            return Ok(FsckReport {
+                problems,
+                repaired: false,
+            });
+        }
+
+        self.current_fs_block = Some(block);
+        self.update_fs()?;
+        Ok(FsckReport {
+            problems,
+            repaired: true,
+        })
+    }
+
+    // builds an empty FS from scratch and commits it over the next FS copy, so
+    // it has to be allowed to write system blocks. the SKSA's FAT entries and
+    // any bad block marks come from the cached FS; scan_spares also reads every
+    // block's spare for bad marks, which is the only source when the FS is too
+    // broken to have been loaded (the SKSA's entries are then just Reserved)
+    pub(super) fn format_fs(&mut self, scan_spares: bool) -> Result<()> {
+        self.check_writable(FS_START_BLOCK)?;
+
+        let mut fat = [FATEntry::Free; 0x1000];
+        fat[..SKSA_BLOCKS as usize].fill(FATEntry::Reserved);
+        fat[FS_START_BLOCK as usize..].fill(FATEntry::Reserved);
+
+        // whatever's there may still be picked over this one unless it's newer,
+        // and the cached FS isn't refreshed first so it may not be the newest
+        let mut seqno = self
+            .list_fs_copies()?
+            .iter()
+            .filter(|copy| copy.magic_valid)
+            .map(|copy| copy.seqno)
+            .max()
+            .unwrap_or(0);
+        if let Some(block) = &self.current_fs_block {
+            fat[..SKSA_BLOCKS as usize].copy_from_slice(&block.fat[..SKSA_BLOCKS as usize]);
+            for (index, entry) in block.fat.iter().enumerate() {
+                if *entry == FATEntry::BadBlock {
+                    fat[index] = FATEntry::BadBlock;
+                }
+            }
+            seqno = seqno.max(block.footer.seqno);
+        }
+
+        if scan_spares || self.current_fs_block.is_none() {
+            let spares = self.dump_spares()?;
+            let data_area = SKSA_BLOCKS as usize..FS_START_BLOCK as usize;
+            for (index, spare) in spares.chunks(SPARE_SIZE).enumerate() {
+                if spare[5] != 0xFF && data_area.contains(&index) {
+                    fat[index] = FATEntry::BadBlock;
+                }
+            }
+        }
+
+        self.current_fs_block = Some(FSBlock {
+            fat,
+            entries: std::array::from_fn(|_| FileEntry {
+                name: [0; 8],
+                ext: [0; 3],
+                valid: FileValid::Invalid,
+                start: FATEntry::Free,
+                size: 0,
+            }),
+            footer: FSFooter {
+                fs_type: FSType::Bbfs,
+                seqno,
+                link_block: 0,
+                chksum: 0,
+            },
+        });
+        if self.current_fs_spare.len() != SPARE_SIZE {
+            self.current_fs_spare = BLANK_SPARE.to_vec();
+        }
+        self.update_fs()
+    }
+
+    pub(super) fn defragment(&mut self) -> Result<DefragReport> {
+        let mut fragmented = vec![];
+        for file in self.list_entries()? {
+            let chain = match self.list_file_blocks(&file.name)? {
+                Some(c) => c,
+                None => continue,
+            };
+            let required_blocks = Self::bytes_to_blocks(file.size as usize);
+            if required_blocks == 0 || chain.len() < required_blocks {
+                continue;
+            }
+            if chain[..required_blocks]
+                .windows(2)
+                .all(|w| w[0].wrapping_add(1) == w[1])
+            {
+                continue;
+            }
+            fragmented.push((file.name, chain[..required_blocks].to_vec()));
+        }
+
+        // the new chains are linked into the cached FAT as they're planned, with
+        // no entry pointing at them yet. if anything fails before they're
+        // committed, put the FAT back so the next commit doesn't leak them
+        let saved = self.current_fs_block.clone();
+        let mut report = DefragReport::default();
+        let moves = match self
+            .plan_defrag_moves(fragmented, &mut report)
+            .and_then(|moves| self.copy_defrag_moves(&moves).map(|_| moves))
+        {
+            Ok(moves) => moves,
+            Err(e) => {
+                self.current_fs_block = saved;
+                return Err(e);
+            }
+        };
+
+        let mut old_chains = vec![];
+        for (filename, chain, blocks_to_write) in moves {
+            if let Some(file) = self.get_file(&filename)? {
+                old_chains.push(file.start);
+                file.start = FATEntry::Chain(blocks_to_write[0]);
+            }
+            report.moved.push((filename, chain.len()));
+        }
+
+        // old chains stay allocated until now so that no new copy can land on them
+        for start in old_chains {
+            self.free_blocks(start);
+        }
+
+        if !report.moved.is_empty() {
+            self.update_fs()?;
+        }
+
+        Ok(report)
+    }
+
+    fn check_file_entry(&self, index: usize) -> Result<()> {
+        if let Some(block) = &self.current_fs_block {
+            let entry = &block.entries[index];
+            if !entry.valid() {
+                return Ok(());
+            }
+
+            let filename = entry.get_fullname();
+            if block.entries.iter().enumerate().any(|(i, e)| {
+                i != index && e.valid() && e.get_fullname().eq_ignore_ascii_case(&filename)
+            }) {
+                return Err(LibBBError::FileAlreadyExists(filename));
+            }
+
+            let mut chain_len = 0;
+            let mut next_block = entry.start;
+            while let FATEntry::Chain(b) = next_block {
+                if !in_data_area(b) || chain_len >= block.fat.len() {
+                    return Err(LibBBError::InvalidStartBlock(filename, b));
+                }
+                chain_len += 1;
+                next_block = block.fat[b as usize];
+            }
+            if Self::bytes_to_blocks(entry.size as usize) > chain_len {
+                return Err(LibBBError::FileSizeExceedsChain(
+                    filename, entry.size, chain_len,
+                ));
+            }
+
+            Ok(())
+        } else {
+            Err(LibBBError::NoFSBlock)
+        }
+    }
+
+    pub(super) fn patch_file_entry<F: FnOnce(&mut FileEntry)>(
+        &mut self,
+        filename: &str,
+        f: F,
+    ) -> Result<()> {
+        let index = match self.find_file_index(filename)? {
+            Some(i) => i,
+            None => return Err(LibBBError::FileNotFound(filename.to_string())),
+        };
+
+        let original = if let Some(block) = &mut self.current_fs_block {
+            let original = block.entries[index].clone();
+            f(&mut block.entries[index]);
+            original
+        } else {
+            return Err(LibBBError::NoFSBlock);
+        };
+
+        if let Err(e) = self.check_file_entry(index) {
+            if let Some(block) = &mut self.current_fs_block {
+                block.entries[index] = original;
+            }
+            return Err(e);
+        }
+
+        // invalidating an entry deletes the file, so its chain has to go too
+        if let Some(block) = &self.current_fs_block
+            && !block.entries[index].valid()
+        {
+            self.free_blocks(original.start);
+        }
+
+        self.update_fs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blank_fs() -> FSBlock {
+        let mut fat = [FATEntry::Free; 0x1000];
+        fat[..SKSA_BLOCKS as usize].fill(FATEntry::Reserved);
+        fat[FS_START_BLOCK as usize..].fill(FATEntry::Reserved);
+        FSBlock {
+            fat,
+            entries: std::array::from_fn(|_| FileEntry {
+                name: [0; 8],
+                ext: [0; 3],
+                valid: FileValid::Invalid,
+                start: FATEntry::Free,
+                size: 0,
+            }),
+            footer: FSFooter {
+                fs_type: FSType::Bbfs,
+                seqno: 1,
+                link_block: 0,
+                chksum: 0,
+            },
+        }
+    }
+
+    // links chain up in the FAT and gives it an entry just big enough for it
+    fn add_file(fs: &mut FSBlock, filename: &str, chain: &[u16]) {
+        for pair in chain.windows(2) {
+            fs.fat[pair[0] as usize] = FATEntry::Chain(pair[1]);
+        }
+        fs.fat[chain[chain.len() - 1] as usize] = FATEntry::EndOfChain;
+
+        let entry = fs.entries.iter_mut().find(|e| !e.valid()).unwrap();
+        entry.set_filename(filename).unwrap();
+        entry.valid = FileValid::Valid;
+        entry.start = FATEntry::Chain(chain[0]);
+        entry.size = (chain.len() * BLOCK_SIZE) as u32;
+    }
+
+    #[test]
+    fn check_passes_a_clean_fs() {
+        let mut fs = blank_fs();
+        add_file(&mut fs, "a.bin", &[0x40, 0x41, 0x42]);
+        add_file(&mut fs, "b.bin", &[0x50, 0x43]);
+        assert_eq!(fs.check(false), vec![]);
+    }
+
+    #[test]
+    fn check_finds_cross_links() {
+        let mut fs = blank_fs();
+        add_file(&mut fs, "a.bin", &[0x40, 0x41]);
+        add_file(&mut fs, "b.bin", &[0x50, 0x41]);
+        assert_eq!(
+            fs.check(false),
+            vec![
+                FsProblem::CrossLinked {
+                    block: 0x41,
+                    files: ("a.bin".to_string(), "b.bin".to_string()),
+                },
+                FsProblem::SizeMismatch {
+                    file: "b.bin".to_string(),
+                    size: 2 * BLOCK_SIZE as u32,
+                    blocks: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn check_finds_and_frees_lost_chains() {
+        let mut fs = blank_fs();
+        add_file(&mut fs, "a.bin", &[0x40]);
+        fs.fat[0x60] = FATEntry::Chain(0x61);
+        fs.fat[0x61] = FATEntry::EndOfChain;
+        assert_eq!(
+            fs.check(true),
+            vec![FsProblem::LostBlocks(vec![0x60, 0x61])]
+        );
+        assert_eq!(fs.fat[0x60], FATEntry::Free);
+        assert_eq!(fs.fat[0x61], FATEntry::Free);
+        assert_eq!(fs.check(false), vec![]);
+    }
+
+    #[test]
+    fn check_finds_bad_terminators() {
+        let mut fs = blank_fs();
+        add_file(&mut fs, "a.bin", &[0x40, 0x41]);
+        fs.fat[0x41] = FATEntry::Free;
+        assert_eq!(
+            fs.check(false)[0],
+            FsProblem::Unterminated {
+                file: "a.bin".to_string(),
+                block: 0x41,
+            }
+        );
+
+        // repairing ends the chain where it went wrong
+        fs.check(true);
+        assert_eq!(fs.fat[0x41], FATEntry::EndOfChain);
+        assert_eq!(fs.check(false), vec![]);
+    }
+
+    #[test]
+    fn check_finds_loops_and_links_out_of_the_data_area() {
+        let mut fs = blank_fs();
+        add_file(&mut fs, "a.bin", &[0x40, 0x41]);
+        fs.fat[0x41] = FATEntry::Chain(0x40);
+        add_file(&mut fs, "b.bin", &[0x50, 0x51]);
+        fs.fat[0x50] = FATEntry::Chain(0x10);
+        let problems = fs.check(false);
+        assert!(problems.contains(&FsProblem::Loop {
+            file: "a.bin".to_string(),
+            block: 0x40,
+        }));
+        assert!(problems.contains(&FsProblem::OutOfRange {
+            file: "b.bin".to_string(),
+            block: 0x10,
+        }));
+    }
+
+    #[test]
+    fn fragmentation_of_an_empty_fs_is_zero() {
+        let report = blank_fs().fragmentation();
+        assert_eq!(report.files, vec![]);
+        assert_eq!(report.fragmented_files, 0);
+        assert_eq!(report.score, 0.0);
+    }
+
+    #[test]
+    fn fragmentation_counts_runs_per_file() {
+        let mut fs = blank_fs();
+        add_file(&mut fs, "a.bin", &[0x40, 0x41, 0x42]);
+        add_file(&mut fs, "b.bin", &[0x50, 0x51, 0x43, 0x60, 0x61]);
+        add_file(&mut fs, "c.bin", &[0x70]);
+        let report = fs.fragmentation();
+
+        let file = |name: &str, blocks, fragments, longest_run| FileFragmentation {
+            name: name.to_string(),
+            blocks,
+            fragments,
+            longest_run,
+        };
+        assert_eq!(
+            report.files,
+            vec![
+                file("a.bin", 3, 1, 3),
+                file("b.bin", 5, 3, 2),
+                file("c.bin", 1, 1, 1),
+            ]
+        );
+        assert_eq!(report.fragmented_files, 1);
+        // two of the six links between blocks jump somewhere else
+        assert_eq!(report.score, 2.0 / 6.0);
+    }
+
+    #[test]
+    fn fragmentation_of_a_backwards_chain() {
+        let mut fs = blank_fs();
+        add_file(&mut fs, "a.bin", &[0x42, 0x41, 0x40]);
+        let report = fs.fragmentation();
+        assert_eq!(report.files[0].fragments, 3);
+        assert_eq!(report.files[0].longest_run, 1);
+        assert_eq!(report.score, 1.0);
+    }
+
+    #[test]
+    fn pattern_matches_ignoring_case() {
+        assert!(matches_pattern("GAME.REC", "game.rec"));
+        assert!(matches_pattern("game.rec", "GAME.REC"));
+        assert!(!matches_pattern("game.rec", "game.sta"));
+    }
+
+    #[test]
+    fn star_matches_at_either_end() {
+        assert!(matches_pattern("*.rec", "save.rec"));
+        assert!(matches_pattern("sa*.rec", "save.rec"));
+        assert!(matches_pattern("*ve.rec", "save.rec"));
+        assert!(matches_pattern("s*e.rec", "se.rec"));
+        assert!(matches_pattern("*ab", "aab"));
+        assert!(!matches_pattern("*b", "abc"));
+        assert!(wildcard_match(b"*", b""));
+        assert!(!wildcard_match(b"", b"a"));
+    }
+
+    #[test]
+    fn name_and_extension_match_separately() {
+        assert!(!matches_pattern("*.rec", "a.b.rec"));
+        assert!(!matches_pattern("*.rec", "rec"));
+        assert!(matches_pattern("*.*", "noext"));
+        assert!(matches_pattern("save.*", "save.rec"));
+        assert!(!matches_pattern("save.?", "save.rec"));
+        assert!(matches_pattern("save.???", "save.rec"));
+    }
+
+    #[test]
+    fn pattern_without_a_dot_covers_the_whole_name() {
+        assert!(matches_pattern("*", "save.rec"));
+        assert!(matches_pattern("sa*", "save.rec"));
+        assert!(matches_pattern("a?b", "a.b"));
+        assert!(!matches_pattern("a?.b", "a.b"));
+        assert!(!matches_pattern("save", "save.rec"));
+    }
 }
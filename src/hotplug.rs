@@ -0,0 +1,133 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread::{self, sleep, JoinHandle},
+};
+
+use rusb::{Device, GlobalContext, Hotplug, HotplugBuilder, UsbContext};
+
+use crate::{
+    constants::{BB_PRODUCT_ID, HOTPLUG_POLL_INTERVAL, IQUE_VENDOR_ID},
+    error::Result,
+    usb::UsbTransport,
+    BBPlayer,
+};
+
+pub enum HotplugEvent<C: UsbContext = GlobalContext> {
+    Arrived(Device<C>),
+    Left(Device<C>),
+}
+
+impl<C: UsbContext> fmt::Debug for HotplugEvent<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Arrived(d) => f.debug_tuple("Arrived").field(d).finish(),
+            Self::Left(d) => f.debug_tuple("Left").field(d).finish(),
+        }
+    }
+}
+
+struct Forwarder<C: UsbContext> {
+    events: Sender<HotplugEvent<C>>,
+}
+
+impl<C: UsbContext> Hotplug<C> for Forwarder<C> {
+    fn device_arrived(&mut self, device: Device<C>) {
+        let _ = self.events.send(HotplugEvent::Arrived(device));
+    }
+
+    fn device_left(&mut self, device: Device<C>) {
+        let _ = self.events.send(HotplugEvent::Left(device));
+    }
+}
+
+pub struct HotplugMonitor<C: UsbContext = GlobalContext> {
+    events: Receiver<HotplugEvent<C>>,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl HotplugMonitor {
+    pub fn new() -> Result<Self> {
+        Self::with_context(GlobalContext::default())
+    }
+}
+
+impl<C: UsbContext + 'static> HotplugMonitor<C> {
+    pub fn with_context(context: C) -> Result<Self> {
+        let (tx, rx) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let worker = if rusb::has_hotplug() {
+            let registration = HotplugBuilder::new()
+                .vendor_id(IQUE_VENDOR_ID)
+                .product_id(BB_PRODUCT_ID)
+                .enumerate(true)
+                .register(context.clone(), Box::new(Forwarder { events: tx }))?;
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let _registration = registration;
+                while !stop.load(Ordering::Relaxed) {
+                    if context.handle_events(Some(HOTPLUG_POLL_INTERVAL)).is_err() {
+                        break;
+                    }
+                }
+            })
+        } else {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || Self::poll(context, tx, &stop))
+        };
+
+        Ok(Self {
+            events: rx,
+            stop,
+            worker: Some(worker),
+        })
+    }
+
+    fn same_device(a: &Device<C>, b: &Device<C>) -> bool {
+        a.bus_number() == b.bus_number() && a.address() == b.address()
+    }
+
+    // fallback for platforms where libusb has no hotplug support
+    fn poll(context: C, events: Sender<HotplugEvent<C>>, stop: &AtomicBool) {
+        let mut known: Vec<Device<C>> = vec![];
+        while !stop.load(Ordering::Relaxed) {
+            if let Ok(devices) = BBPlayer::<UsbTransport<C>>::get_players_in(&context) {
+                for device in &devices {
+                    if !known.iter().any(|k| Self::same_device(k, device))
+                        && events.send(HotplugEvent::Arrived(device.clone())).is_err()
+                    {
+                        return;
+                    }
+                }
+                for device in &known {
+                    if !devices.iter().any(|d| Self::same_device(d, device))
+                        && events.send(HotplugEvent::Left(device.clone())).is_err()
+                    {
+                        return;
+                    }
+                }
+                known = devices;
+            }
+            sleep(HOTPLUG_POLL_INTERVAL);
+        }
+    }
+
+    pub fn events(&self) -> &Receiver<HotplugEvent<C>> {
+        &self.events
+    }
+}
+
+impl<C: UsbContext> Drop for HotplugMonitor<C> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
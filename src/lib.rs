@@ -4,26 +4,129 @@
 
 use chrono::prelude::*;
 use commands::BlockSpare;
-use std::mem::size_of;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+use std::{
+    cell::{Cell, RefCell},
+    mem::size_of,
+    sync::{atomic::AtomicBool, mpsc::Sender},
+    time::{Duration, Instant},
+};
 
 use error::{LibBBError, Result};
+use events::RetryHookHandle;
 use fs::FSBlock;
-use rusb::{Device, DeviceHandle, DeviceList, GlobalContext};
-
+use log::{debug, error};
+use metrics::SinkHandle;
+use transport::Transport;
+
+#[cfg(feature = "async")]
+mod async_player;
+mod builder;
+mod capabilities;
+mod capture;
 pub(crate) mod commands;
+mod config;
 pub(crate) mod constants;
+mod debug_console;
+#[cfg(feature = "rusb")]
+pub mod diagnostics;
 pub mod error;
+mod events;
+mod filename;
 mod fs;
+#[cfg(feature = "rusb")]
+mod hotplug;
+mod lock;
+mod metrics;
+#[cfg(feature = "nusb")]
+mod nusb;
 mod player_comms;
+#[cfg(feature = "raw-commands")]
+mod raw;
+mod remote;
+mod replies;
+mod stats;
+mod stream;
+mod transcript;
+pub mod transport;
+#[cfg(feature = "rusb")]
 mod usb;
+mod worker;
+
+#[cfg(feature = "async")]
+pub use async_player::AsyncBBPlayer;
+pub use builder::BBPlayerBuilder;
+pub use capabilities::Capabilities;
+pub use capture::CaptureTransport;
+pub use commands::{Command, ConnectionState, LedState, PlannedWrite, Signature, WriteOutcome};
+pub use config::{
+    FsRefreshPolicy, LedFeedback, ReadyPolicy, RetryPolicy, SessionPolicy, SpareWriteMode,
+    TimeoutConfig, WriteMode,
+};
+pub use debug_console::DebugConsole;
+pub use error::{udev_rule, ConsoleError, ErrorClass};
+pub use events::{Event, RetryHook};
+pub use filename::FatFilename;
+pub use fs::{
+    BlockState, ChainLink, DefragReport, FatView, FileEntry, FileFragmentation, FileInfo, Files,
+    FragmentationReport, FsCopy, FsImage, FsProblem, FsStats, FsckReport, MappedFile,
+    RecoverableFile, RecoverySource, WriteProgress,
+};
+#[cfg(feature = "rusb")]
+pub use hotplug::{HotplugEvent, HotplugMonitor};
+pub use metrics::{CommandMetrics, MetricsSink};
+#[cfg(feature = "nusb")]
+pub use nusb::NusbTransport;
+#[cfg(feature = "raw-commands")]
+pub use raw::CommandReply;
+pub use remote::{serve_remote, RemoteTransport};
+pub use stats::{BulkProgress, TransferStats};
+pub use stream::{FileReader, FileWriter};
+pub use transcript::Transcript;
+#[cfg(feature = "rusb")]
+pub use usb::{DeviceIdentity, Endpoints, PlayerInfo, UsbTransport};
+pub use worker::{PlayerWorker, WorkerHandle};
+
+#[cfg(not(any(feature = "rusb", feature = "nusb")))]
+compile_error!("at least one USB backend feature (\"rusb\" or \"nusb\") must be enabled");
+
+#[cfg(feature = "rusb")]
+pub type DefaultTransport = UsbTransport;
+#[cfg(all(feature = "nusb", not(feature = "rusb")))]
+pub type DefaultTransport = NusbTransport;
 
 #[derive(Debug)]
-pub struct BBPlayer {
-    handle: DeviceHandle<GlobalContext>,
+pub struct BBPlayer<B: Transport = DefaultTransport> {
+    transport: B,
     current_fs_index: u32,
     current_fs_block: Option<FSBlock>,
     current_fs_spare: Vec<u8>,
     is_initialised: bool,
+    ready_pending: Cell<bool>,
+    timeouts: TimeoutConfig,
+    write_timeout: Cell<Duration>,
+    ready_policy: ReadyPolicy,
+    stats: Cell<TransferStats>,
+    subscribers: RefCell<Vec<Sender<Event>>>,
+    retry_policy: RetryPolicy,
+    metrics: Option<SinkHandle>,
+    last_ret: Cell<Option<i32>>,
+    metered_arg: Cell<Option<u32>>,
+    capabilities: Option<Capabilities>,
+    num_blocks: Cell<Option<u32>>,
+    spare_write_mode: SpareWriteMode,
+    session_policy: SessionPolicy,
+    fs_refresh_policy: FsRefreshPolicy,
+    allow_system_blocks: bool,
+    atomic_writes: bool,
+    dry_run: bool,
+    plan: RefCell<Vec<PlannedWrite>>,
+    led_feedback: Option<LedFeedback>,
+    on_retry: Option<RetryHookHandle>,
+    // from the builder, so reopen opens the console the same way
+    #[cfg(feature = "rusb")]
+    keep_kernel_driver: bool,
 }
 
 trait FromBE {
@@ -48,6 +151,11 @@ macro_rules! check_initialised {
     };
 }
 
+// the RTC counts weekdays from Sunday, like struct tm's tm_wday
+fn device_weekday(weekday: Weekday) -> u8 {
+    weekday.num_days_from_sunday() as u8
+}
+
 fn num_from_arr<T: FromBE, U: AsRef<[u8]>>(data: U) -> T {
     assert!(data.as_ref().len() == size_of::<T>());
     match data.as_ref() {
@@ -57,42 +165,202 @@ fn num_from_arr<T: FromBE, U: AsRef<[u8]>>(data: U) -> T {
 }
 
 impl BBPlayer {
-    pub fn get_players() -> Result<Vec<Device<GlobalContext>>> {
-        let devices = DeviceList::new()?;
-        let mut rv = vec![];
+    pub fn builder() -> BBPlayerBuilder {
+        BBPlayerBuilder::new()
+    }
+}
+
+impl<B: Transport> BBPlayer<B> {
+    pub fn with_transport(transport: B) -> Self {
+        BBPlayerBuilder::new().build(transport)
+    }
+
+    pub fn initialised(&self) -> bool {
+        self.is_initialised
+    }
+
+    pub fn timeouts(&self) -> TimeoutConfig {
+        self.timeouts
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: TimeoutConfig) {
+        self.timeouts = timeouts;
+        self.write_timeout.set(timeouts.block_write);
+    }
+
+    pub fn ready_policy(&self) -> ReadyPolicy {
+        self.ready_policy
+    }
 
-        for device in devices.iter() {
-            if Self::is_bbp(&device)? {
-                rv.push(device);
+    pub fn set_ready_policy(&mut self, ready_policy: ReadyPolicy) {
+        self.ready_policy = ready_policy;
+    }
+
+    pub fn session_policy(&self) -> SessionPolicy {
+        self.session_policy
+    }
+
+    pub fn set_session_policy(&mut self, session_policy: SessionPolicy) {
+        self.session_policy = session_policy;
+    }
+
+    pub fn fs_refresh_policy(&self) -> FsRefreshPolicy {
+        self.fs_refresh_policy
+    }
+
+    pub fn set_fs_refresh_policy(&mut self, fs_refresh_policy: FsRefreshPolicy) {
+        self.fs_refresh_policy = fs_refresh_policy;
+    }
+
+    pub fn led_feedback(&self) -> Option<LedFeedback> {
+        self.led_feedback
+    }
+
+    pub fn set_led_feedback(&mut self, led_feedback: Option<LedFeedback>) {
+        self.led_feedback = led_feedback;
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    // with dry run on, WriteFile, DeleteFile, WriteSingleBlock and WriteNAND go
+    // through all their checks and planning but only record the block writes
+    // they'd have made; take_plan hands those over and starts a new plan
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    pub fn take_plan(&self) -> Vec<PlannedWrite> {
+        self.plan.take()
+    }
+
+    pub fn allow_system_blocks(&self) -> bool {
+        self.allow_system_blocks
+    }
+
+    // lets WriteSingleBlock and friends write the SKSA and filesystem blocks
+    pub fn set_allow_system_blocks(&mut self, allow: bool) {
+        self.allow_system_blocks = allow;
+    }
+
+    pub fn atomic_writes(&self) -> bool {
+        self.atomic_writes
+    }
+
+    // with this set, WriteFile leaves any file it's replacing alone until the
+    // new one has been written and checked, then swaps them in one FS commit.
+    // the old file's blocks can't be reused, so there has to be room for both
+    pub fn set_atomic_writes(&mut self, atomic: bool) {
+        self.atomic_writes = atomic;
+    }
+
+    pub fn spare_write_mode(&self) -> SpareWriteMode {
+        self.spare_write_mode
+    }
+
+    pub fn set_spare_write_mode(&mut self, spare_write_mode: SpareWriteMode) {
+        self.spare_write_mode = spare_write_mode;
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = RetryPolicy {
+            attempts: retry_policy.attempts.max(1),
+            ..retry_policy
+        };
+    }
+
+    // a cheap round trip (GetSeqNo) that works whether or not Init has been run
+    pub fn ping(&self) -> Result<Duration> {
+        let start = Instant::now();
+        self.get_seqno()?;
+        Ok(start.elapsed())
+    }
+
+    // like ping, but drains anything left over from an earlier command first, so
+    // a stray reply or ready signal can't be mistaken for this one's. worth
+    // calling before starting anything long, like a full dump
+    #[allow(non_snake_case)]
+    pub fn Ping(&self) -> Result<Duration> {
+        self.resync()?;
+        self.ping()
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        match self.ping() {
+            Ok(_) => ConnectionState::Connected,
+            Err(e) if e.is_disconnect() => ConnectionState::Gone,
+            Err(_) => {
+                // whatever the console was doing, don't leave half a reply behind
+                match self.resync() {
+                    Err(e) if e.is_disconnect() => ConnectionState::Gone,
+                    _ => ConnectionState::Busy,
+                }
             }
         }
+    }
 
-        Ok(rv)
+    // the console numbers host sessions; Init starts one at 1. neither needs Init
+    // to have been run first
+    #[allow(non_snake_case)]
+    pub fn GetSeqNo(&self) -> Result<u32> {
+        self.get_seqno()
     }
 
-    pub fn new(device: &Device<GlobalContext>) -> Result<Self> {
-        Ok(Self {
-            handle: Self::open_device(device)?,
-            current_fs_index: 0,
-            current_fs_block: None,
-            current_fs_spare: vec![],
-            is_initialised: false,
-        })
+    #[allow(non_snake_case)]
+    pub fn SetSeqNo(&self, seqno: u32) -> Result<()> {
+        self.set_seqno(seqno)
     }
 
-    pub fn initialised(&self) -> bool {
-        self.is_initialised
+    // moves the session on by one and returns the new seqno
+    #[allow(non_snake_case)]
+    pub fn BumpSeqNo(&self) -> Result<u32> {
+        let seqno = self.get_seqno()?.wrapping_add(1);
+        self.set_seqno(seqno)?;
+        Ok(seqno)
+    }
+
+    // fails if something else has changed the seqno since it was last seen
+    #[allow(non_snake_case)]
+    pub fn ValidateSeqNo(&self, expected: u32) -> Result<()> {
+        let seqno = self.get_seqno()?;
+        if seqno != expected {
+            return Err(LibBBError::SeqNoMismatch(expected, seqno));
+        }
+        Ok(())
     }
 
     #[allow(non_snake_case)]
     pub fn Init(&mut self) -> Result<()> {
-        self.set_seqno(0x01)?;
+        // a console that's already in a session (say, one we reopened) doesn't
+        // need resetting
+        if self.session_policy == SessionPolicy::Reset {
+            self.resync()?;
+        }
+        let seqno = self.get_seqno()?;
+        if seqno != 0x01 {
+            match self.session_policy {
+                SessionPolicy::Reset => {
+                    debug!("console seqno is {seqno}, starting a new session");
+                    self.set_seqno(0x01)?;
+                }
+                SessionPolicy::Keep => debug!("console seqno is {seqno}, keeping its session"),
+                SessionPolicy::Refuse if seqno != 0 => {
+                    return Err(LibBBError::SessionInProgress(seqno))
+                }
+                SessionPolicy::Refuse => self.set_seqno(0x01)?,
+            }
+        }
         self.get_num_blocks()?;
         if !self.get_current_fs()? {
             return Err(LibBBError::FS);
         }
         self.init_fs()?;
-        self.delete_file_and_update("temp.tmp")?;
+        self.delete_temp_files()?;
         self.is_initialised = true;
         Ok(())
     }
@@ -103,11 +371,22 @@ impl BBPlayer {
     }
 
     #[allow(non_snake_case)]
-    pub fn SetLED(&self, ledval: u32) -> Result<()> {
-        check_initialised!(self.is_initialised, { self.set_led(ledval) })
+    pub fn FirmwareVersion(&self) -> Result<String> {
+        check_initialised!(self.is_initialised, { self.firmware_version() })
     }
 
-    // signhash
+    #[allow(non_snake_case)]
+    pub fn SetLED<T: Into<LedState>>(&self, state: T) -> Result<()> {
+        check_initialised!(self.is_initialised, { self.set_led(state.into()) })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn SignHash(&self, hash: [u8; 20]) -> Result<Signature> {
+        check_initialised!(self.is_initialised, {
+            self.require(Command::SignHash)?;
+            self.sign_hash(hash)
+        })
+    }
 
     #[allow(non_snake_case)]
     pub fn SetTime<Tz: TimeZone>(&self, when: DateTime<Tz>) -> Result<()> {
@@ -116,7 +395,7 @@ impl BBPlayer {
                 (when.year() % 100) as u8,
                 when.month() as u8,
                 when.day() as u8,
-                when.weekday() as u8,
+                device_weekday(when.weekday()),
                 0,
                 when.hour() as u8,
                 when.minute() as u8,
@@ -130,31 +409,162 @@ impl BBPlayer {
     #[allow(non_snake_case)]
     pub fn ListFileBlocks<T: AsRef<str>>(&self, filename: T) -> Result<Option<Vec<u16>>> {
         check_initialised!(self.is_initialised, {
+            let filename = FatFilename::new(filename)?;
             self.list_file_blocks(filename.as_ref())
         })
     }
 
+    #[allow(non_snake_case)]
+    pub fn FileChain<T: AsRef<str>>(&self, filename: T) -> Result<Option<Vec<ChainLink>>> {
+        check_initialised!(self.is_initialised, {
+            let filename = FatFilename::new(filename)?;
+            self.file_chain(filename.as_ref())
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn ListFileEntries(&self) -> Result<Vec<FileInfo>> {
+        check_initialised!(self.is_initialised, { self.list_entries() })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn StatFile<T: AsRef<str>>(&self, filename: T) -> Result<Option<FileInfo>> {
+        check_initialised!(self.is_initialised, {
+            let filename = FatFilename::new(filename)?;
+            self.stat_file(filename.as_ref())
+        })
+    }
+
+    // from the cached FS, so no commands are sent
+    #[allow(non_snake_case)]
+    pub fn ViewFAT(&self) -> Result<FatView> {
+        check_initialised!(self.is_initialised, { self.view_fat() })
+    }
+
+    // also from the cached FS. a high score or a lot of fragmented files is the
+    // sign that Defragment is worth running
+    #[allow(non_snake_case)]
+    pub fn FragmentationReport(&self) -> Result<FragmentationReport> {
+        check_initialised!(self.is_initialised, { self.fragmentation_report() })
+    }
+
+    // walks the cached FS, so it's only as current as the last FS commit
+    pub fn files(&self) -> Result<Files<'_>> {
+        check_initialised!(self.is_initialised, { self.cached_files() })
+    }
+
+    #[deprecated(note = "use ListFileEntries, which also has each file's blocks")]
     #[allow(non_snake_case)]
     pub fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
         check_initialised!(self.is_initialised, { self.list_files() })
     }
 
+    // see matches_pattern for what a pattern can contain
+    #[allow(non_snake_case)]
+    pub fn ListMatching<T: AsRef<str>>(&self, pattern: T) -> Result<Vec<FileInfo>> {
+        check_initialised!(self.is_initialised, {
+            self.list_matching(pattern.as_ref())
+        })
+    }
+
     #[allow(non_snake_case)]
     pub fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
         check_initialised!(self.is_initialised, { self.dump_current_fs() })
     }
 
+    #[allow(non_snake_case)]
+    pub fn ListFSCopies(&self) -> Result<Vec<FsCopy>> {
+        check_initialised!(self.is_initialised, { self.list_fs_copies() })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn ListFSGenerations(&self) -> Result<Vec<FsCopy>> {
+        check_initialised!(self.is_initialised, { self.list_fs_generations() })
+    }
+
+    // block is an FsCopy's block, as listed by ListFSGenerations
+    #[allow(non_snake_case)]
+    pub fn RollbackFS(&mut self, block: u32) -> Result<()> {
+        check_initialised!(self.is_initialised, {
+            self.with_fs_rollback(|player| player.rollback_fs(block))
+        })
+    }
+
     #[allow(non_snake_case)]
     pub fn DumpNAND(&self) -> Result<BlockSpare> {
         check_initialised!(self.is_initialised, { self.dump_nand_and_spare() })
     }
 
+    #[allow(non_snake_case)]
+    pub fn DumpNANDCancellable(&self, cancel: &AtomicBool) -> Result<BlockSpare> {
+        check_initialised!(self.is_initialised, {
+            self.dump_nand_and_spare_cancellable(cancel)
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn DumpSpares(&self) -> Result<Vec<u8>> {
+        check_initialised!(self.is_initialised, { self.dump_spares() })
+    }
+
+    #[cfg(feature = "mmap")]
+    #[allow(non_snake_case)]
+    pub fn DumpNANDToMmap<P: AsRef<Path>>(&self, path: P) -> Result<memmap2::Mmap> {
+        check_initialised!(self.is_initialised, {
+            self.dump_nand_to_mmap(path.as_ref())
+        })
+    }
+
     #[allow(non_snake_case)]
     pub fn ReadSingleBlock(&self, block_num: u32) -> Result<BlockSpare> {
-        check_initialised!(self.is_initialised, { self.read_single_block(block_num) })
+        check_initialised!(self.is_initialised, {
+            self.check_block_num(block_num)?;
+            self.read_single_block(block_num)
+        })
     }
 
-    // WriteNAND
+    #[allow(non_snake_case)]
+    pub fn ReadRawBlock(&self, block_num: u32) -> Result<Vec<u8>> {
+        check_initialised!(self.is_initialised, {
+            self.require(Command::ReadBlock)?;
+            self.check_block_num(block_num)?;
+            self.read_raw_block(block_num)
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn WriteRawBlock<T: AsRef<[u8]>>(&self, block: T, block_num: u32) -> Result<()> {
+        check_initialised!(self.is_initialised, {
+            self.require(Command::WriteBlock)?;
+            self.check_block_num(block_num)?;
+            self.check_writable(block_num)?;
+            self.write_raw_block(block.as_ref(), block_num)
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn WriteNAND<T: AsRef<[u8]>, U: AsRef<[u8]>>(&mut self, nand: T, spare: U) -> Result<()> {
+        check_initialised!(self.is_initialised, {
+            self.require(Command::WriteBlockAndSpare)?;
+            self.write_nand_and_spare(nand.as_ref(), spare.as_ref(), 0, |_, _| {})?;
+            self.reload_restored_fs()
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn ResumeWriteNAND<T: AsRef<[u8]>, U: AsRef<[u8]>, F: FnMut(u32, WriteOutcome)>(
+        &mut self,
+        nand: T,
+        spare: U,
+        start: u32,
+        on_block: F,
+    ) -> Result<()> {
+        check_initialised!(self.is_initialised, {
+            self.require(Command::WriteBlockAndSpare)?;
+            self.write_nand_and_spare(nand.as_ref(), spare.as_ref(), start, on_block)?;
+            self.reload_restored_fs()
+        })
+    }
 
     #[allow(non_snake_case)]
     pub fn WriteSingleBlock<T: AsRef<[u8]>, U: AsRef<[u8]>>(
@@ -162,36 +572,268 @@ impl BBPlayer {
         block: T,
         spare: U,
         block_num: u32,
+        verify: bool,
     ) -> Result<()> {
         check_initialised!(self.is_initialised, {
-            self.write_single_block(block.as_ref(), spare.as_ref(), block_num)
+            self.require(Command::WriteBlockAndSpare)?;
+            self.check_block_num(block_num)?;
+            self.check_writable(block_num)?;
+            self.write_single_block(block.as_ref(), spare.as_ref(), block_num, verify)
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn WriteSingleBlockPreservingSpare<T: AsRef<[u8]>, F: FnOnce(&mut [u8])>(
+        &self,
+        block: T,
+        block_num: u32,
+        verify: bool,
+        update_spare: F,
+    ) -> Result<()> {
+        check_initialised!(self.is_initialised, {
+            self.require(Command::WriteBlockAndSpare)?;
+            self.check_block_num(block_num)?;
+            self.check_writable(block_num)?;
+            self.write_single_block_preserving_spare(
+                block.as_ref(),
+                block_num,
+                verify,
+                update_spare,
+            )
         })
     }
 
     #[allow(non_snake_case)]
     pub fn ReadFile<T: AsRef<str>>(&self, filename: T) -> Result<Option<Vec<u8>>> {
-        check_initialised!(self.is_initialised, { self.read_file(filename.as_ref()) })
+        check_initialised!(self.is_initialised, {
+            let filename = FatFilename::new(filename)?;
+            self.read_file(filename.as_ref())
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn ReadFileRange<T: AsRef<str>>(
+        &self,
+        filename: T,
+        offset: u32,
+        len: u32,
+    ) -> Result<Option<Vec<u8>>> {
+        check_initialised!(self.is_initialised, {
+            let filename = FatFilename::new(filename)?;
+            self.read_file_range(filename.as_ref(), offset, len)
+        })
+    }
+
+    // the checksum is the wrapping sum of every byte in the file, and size is
+    // the length in bytes the console should sum over
+    #[allow(non_snake_case)]
+    pub fn VerifyFile<T: AsRef<str>>(&self, filename: T, checksum: u32, size: u32) -> Result<bool> {
+        check_initialised!(self.is_initialised, {
+            let filename = FatFilename::new(filename)?;
+            self.file_checksum_cmp(filename.as_ref(), checksum, size)
+        })
     }
 
     #[allow(non_snake_case)]
     pub fn WriteFile<T: AsRef<[u8]>, U: AsRef<str>>(&mut self, data: T, filename: U) -> Result<()> {
+        self.WriteFileWith(data, filename, WriteMode::Overwrite)
+    }
+
+    #[allow(non_snake_case)]
+    pub fn WriteFileWith<T: AsRef<[u8]>, U: AsRef<str>>(
+        &mut self,
+        data: T,
+        filename: U,
+        mode: WriteMode,
+    ) -> Result<()> {
+        check_initialised!(self.is_initialised, {
+            let filename = FatFilename::new(filename)?;
+            if mode == WriteMode::CreateNew && self.find_file(filename.as_ref())?.is_some() {
+                return Err(LibBBError::FileAlreadyExists(filename.to_string()));
+            }
+            self.with_fs_rollback(|player| player.write_file(data.as_ref(), filename.as_ref()))
+        })
+    }
+
+    // pass WriteProgress::default() to start an upload; if it fails partway,
+    // calling this again with the same progress only writes what's left
+    #[allow(non_snake_case)]
+    pub fn ResumeWrite<T: AsRef<str>, U: AsRef<[u8]>>(
+        &mut self,
+        filename: T,
+        data: U,
+        progress: &mut WriteProgress,
+    ) -> Result<()> {
+        check_initialised!(self.is_initialised, {
+            let filename = FatFilename::new(filename)?;
+            self.with_fs_rollback(|player| {
+                player.resume_write(filename.as_ref(), data.as_ref(), progress)
+            })
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn WriteFiles<T: AsRef<str>, U: AsRef<[u8]>>(&mut self, files: &[(T, U)]) -> Result<()> {
+        check_initialised!(self.is_initialised, {
+            let filenames = files
+                .iter()
+                .map(|(filename, _)| FatFilename::new(filename))
+                .collect::<Result<Vec<_>>>()?;
+            let files = filenames
+                .iter()
+                .zip(files)
+                .map(|(filename, (_, data))| (filename.as_str(), data.as_ref()))
+                .collect::<Vec<_>>();
+            self.with_fs_rollback(|player| player.write_files(&files))
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn ReadFiles<T: AsRef<str>>(&self, filenames: &[T]) -> Result<Vec<Option<Vec<u8>>>> {
+        check_initialised!(self.is_initialised, {
+            let filenames = filenames
+                .iter()
+                .map(FatFilename::new)
+                .collect::<Result<Vec<_>>>()?;
+            self.read_files(
+                &filenames
+                    .iter()
+                    .map(FatFilename::as_str)
+                    .collect::<Vec<_>>(),
+            )
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn ReadMatching<T: AsRef<str>>(&self, pattern: T) -> Result<Vec<(String, Vec<u8>)>> {
         check_initialised!(self.is_initialised, {
-            self.write_file(data.as_ref(), filename.as_ref())
+            self.read_matching(pattern.as_ref())
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn DeleteMatching<T: AsRef<str>>(&mut self, pattern: T) -> Result<Vec<String>> {
+        check_initialised!(self.is_initialised, {
+            self.with_fs_rollback(|player| player.delete_matching(pattern.as_ref()))
         })
     }
 
     #[allow(non_snake_case)]
     pub fn DeleteFile<T: AsRef<str>>(&mut self, filename: T) -> Result<()> {
         check_initialised!(self.is_initialised, {
-            self.delete_file_and_update(filename.as_ref())
+            let filename = FatFilename::new(filename)?;
+            self.with_fs_rollback(|player| player.delete_file_and_update(filename.as_ref()))
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn RenameFile<T: AsRef<str>, U: AsRef<str>>(&mut self, from: T, to: U) -> Result<()> {
+        check_initialised!(self.is_initialised, {
+            let (from, to) = (FatFilename::new(from)?, FatFilename::new(to)?);
+            self.with_fs_rollback(|player| {
+                player.rename_file_and_update(from.as_ref(), to.as_ref())
+            })
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn ShredFile<T: AsRef<str>>(&mut self, filename: T) -> Result<()> {
+        check_initialised!(self.is_initialised, {
+            let filename = FatFilename::new(filename)?;
+            self.with_fs_rollback(|player| player.shred_file(filename.as_ref()))
+        })
+    }
+
+    // returns how many blocks were wiped
+    #[allow(non_snake_case)]
+    pub fn WipeFreeSpace(&mut self, fill: u8) -> Result<u32> {
+        self.WipeFreeSpaceCancellable(fill, &AtomicBool::new(false))
+    }
+
+    #[allow(non_snake_case)]
+    pub fn WipeFreeSpaceCancellable(&mut self, fill: u8, cancel: &AtomicBool) -> Result<u32> {
+        // the free list has to come from the console's FS, not a stale copy of
+        // it, or this overwrites live files
+        check_initialised!(self.is_initialised, {
+            self.with_fs_rollback(|player| player.wipe_free_space(fill, cancel))
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn ListRecoverable(&self) -> Result<Vec<RecoverableFile>> {
+        check_initialised!(self.is_initialised, { self.list_recoverable() })
+    }
+
+    // takes a name from ListRecoverable
+    #[allow(non_snake_case)]
+    pub fn UndeleteFile<T: AsRef<str>>(&mut self, filename: T) -> Result<()> {
+        check_initialised!(self.is_initialised, {
+            let filename = FatFilename::new(filename)?;
+            self.with_fs_rollback(|player| player.undelete_file(filename.as_ref()))
         })
     }
 
     #[allow(non_snake_case)]
-    pub fn GetStats(&self) -> Result<(usize, usize, usize, u32)> {
+    pub fn CopyFile<T: AsRef<str>, U: AsRef<str>>(&mut self, from: T, to: U) -> Result<()> {
+        check_initialised!(self.is_initialised, {
+            let (from, to) = (FatFilename::new(from)?, FatFilename::new(to)?);
+            self.with_fs_rollback(|player| player.copy_file(from.as_ref(), to.as_ref()))
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn PatchFileEntry<T: AsRef<str>, F: FnOnce(&mut FileEntry)>(
+        &mut self,
+        filename: T,
+        f: F,
+    ) -> Result<()> {
+        check_initialised!(self.is_initialised, {
+            let filename = FatFilename::new(filename)?;
+            self.with_fs_rollback(|player| player.patch_file_entry(filename.as_ref(), f))
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn CheckFS(&mut self, repair: bool) -> Result<FsckReport> {
+        check_initialised!(self.is_initialised, {
+            self.with_fs_rollback(|player| player.check_fs(repair))
+        })
+    }
+
+    // see set_allow_system_blocks. unlike the console's InitFS command, which
+    // only reloads the FS that's already there, this writes a new one. it also
+    // works after an Init that failed with LibBBError::FS, as long as Init got
+    // as far as reading the block count, and leaves the player initialised
+    #[allow(non_snake_case)]
+    pub fn FormatFS(&mut self, scan_spares: bool) -> Result<()> {
+        if !self.is_initialised && self.num_blocks.get().is_none() {
+            return Err(LibBBError::NoConsole);
+        }
+        // the old FS is thrown away, so there's no point refreshing it
+        self.with_fs_restored(|player| player.format_fs(scan_spares))?;
+        if !self.dry_run {
+            self.is_initialised = true;
+        }
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    pub fn Defragment(&mut self) -> Result<DefragReport> {
+        check_initialised!(self.is_initialised, {
+            self.with_fs_rollback(|player| player.defragment())
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn GetStats(&self) -> Result<FsStats> {
         check_initialised!(self.is_initialised, { self.get_stats() })
     }
 
+    #[allow(non_snake_case)]
+    pub fn Recover(&mut self, reset_device: bool) -> Result<()> {
+        self.recover(reset_device)
+    }
+
     #[allow(non_snake_case)]
     pub fn Close(&mut self) -> Result<()> {
         check_initialised!(self.is_initialised, {
@@ -205,13 +847,13 @@ impl BBPlayer {
     }
 }
 
-impl Drop for BBPlayer {
+impl<B: Transport> Drop for BBPlayer<B> {
     fn drop(&mut self) {
         if self.is_initialised {
             match self.close_connection() {
                 Ok(_) => {}
                 Err(e) => {
-                    eprintln!("{e}");
+                    error!("{e}");
                     return;
                 }
             }
@@ -219,3 +861,21 @@ impl Drop for BBPlayer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Weekday;
+
+    use super::device_weekday;
+
+    #[test]
+    fn device_weekday_counts_from_sunday() {
+        assert_eq!(device_weekday(Weekday::Sun), 0);
+        assert_eq!(device_weekday(Weekday::Mon), 1);
+        assert_eq!(device_weekday(Weekday::Tue), 2);
+        assert_eq!(device_weekday(Weekday::Wed), 3);
+        assert_eq!(device_weekday(Weekday::Thu), 4);
+        assert_eq!(device_weekday(Weekday::Fri), 5);
+        assert_eq!(device_weekday(Weekday::Sat), 6);
+    }
+}
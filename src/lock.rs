@@ -0,0 +1,46 @@
+use std::{
+    fs::{File, OpenOptions},
+    path::PathBuf,
+};
+
+use fs2::FileExt;
+
+use crate::error::{LibBBError, Result};
+
+// an advisory lock on a per-console file in the temp directory, so two processes
+// can't both claim the same console and interleave commands. the OS drops the
+// lock if the process dies, so a crash never leaves the console locked
+#[derive(Debug)]
+pub(crate) struct DeviceLock {
+    file: File,
+}
+
+impl DeviceLock {
+    pub(crate) fn acquire(key: &str) -> Result<Self> {
+        let path = Self::path(key);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        match file.try_lock_exclusive() {
+            Ok(_) => Ok(Self { file }),
+            Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+                Err(LibBBError::Busy(path))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn path(key: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bb-{key}.lock"))
+    }
+}
+
+impl Drop for DeviceLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
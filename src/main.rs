@@ -1,7 +1,7 @@
 use std::fs::{read, write};
 
 use bb::error::Result;
-use bb::BBPlayer;
+use bb::{BBPlayer, FsStats};
 use chrono::Local;
 
 fn main() -> Result<()> {
@@ -21,9 +21,9 @@ fn main() -> Result<()> {
         }
     };
     println!("{blocks:X?}");
-    let files = player.ListFiles()?;
+    let files = player.ListFileEntries()?;
     for file in files {
-        println!("{:>12}: {}", file.0, file.1);
+        println!("{:>12}: {}", file.name, file.size);
     }
     write("current_fs.bin", player.DumpCurrentFS()?).unwrap();
     /*let (nand, spare) = player.DumpNAND()?;
@@ -32,7 +32,8 @@ fn main() -> Result<()> {
     let (block, spare) = player.ReadSingleBlock(0)?;
     write("block0.bin", &block).unwrap();
     write("spare0.bin", &spare).unwrap();
-    player.WriteSingleBlock(block, spare, 0)?;
+    player.set_allow_system_blocks(true);
+    player.WriteSingleBlock(block, spare, 0, true)?;
     /*let file = match player.ReadFile("00bbc0de.rec")? {
         Some(b) => b,
         None => {
@@ -47,7 +48,13 @@ fn main() -> Result<()> {
     player.DeleteFile("testfile.bin")?;
     player.DeleteFile("test")?;
 
-    let (free, used, bad, seqno) = player.GetStats()?;
+    let FsStats {
+        free_blocks: free,
+        used_blocks: used,
+        bad_blocks: bad,
+        seqno,
+        ..
+    } = player.GetStats()?;
     println!("Free: {free} (0x{free:04X})\nUsed: {used} (0x{used:04X})\nBad: {bad} (0x{bad:04X})\nSequence Number: {seqno}");
 
     Ok(())
@@ -0,0 +1,83 @@
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{commands::Command, error::Result, transport::Transport, BBPlayer};
+
+// one command as seen from the host; the byte counts cover everything sent and
+// received on the bulk endpoints for it, retries included. arg is what went out
+// with the command word the first time, and result is the last status word the
+// console replied with, if it sent one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandMetrics {
+    pub command: Command,
+    pub arg: Option<u32>,
+    pub elapsed: Duration,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub retries: u64,
+    pub result: Option<i32>,
+    pub success: bool,
+}
+
+// called on the player's thread once each command finishes, so anything slow
+// belongs on a channel rather than in record itself
+pub trait MetricsSink {
+    fn record(&self, metrics: &CommandMetrics);
+}
+
+impl<F: Fn(&CommandMetrics)> MetricsSink for F {
+    fn record(&self, metrics: &CommandMetrics) {
+        self(metrics)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct SinkHandle(pub(crate) Arc<dyn MetricsSink + Send + Sync>);
+
+impl fmt::Debug for SinkHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MetricsSink")
+    }
+}
+
+impl<B: Transport> BBPlayer<B> {
+    pub fn set_metrics_sink<S: MetricsSink + Send + Sync + 'static>(&mut self, sink: S) {
+        self.metrics = Some(SinkHandle(Arc::new(sink)));
+    }
+
+    pub fn clear_metrics_sink(&mut self) {
+        self.metrics = None;
+    }
+
+    pub(crate) fn metered<T, F: FnOnce() -> Result<T>>(&self, command: Command, f: F) -> Result<T> {
+        let Some(sink) = &self.metrics else {
+            return f();
+        };
+
+        // a metered command can run another (a verified write reads the block
+        // back), so put back what the outer one had seen once this one's done
+        let outer = (self.last_ret.take(), self.metered_arg.take());
+        let before = self.stats.get();
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        let after = self.stats.get();
+
+        sink.0.record(&CommandMetrics {
+            command,
+            arg: self.metered_arg.get(),
+            elapsed,
+            bytes_in: after.bytes_in.saturating_sub(before.bytes_in),
+            bytes_out: after.bytes_out.saturating_sub(before.bytes_out),
+            retries: after.retries.saturating_sub(before.retries),
+            result: self.last_ret.get(),
+            success: result.is_ok(),
+        });
+        self.last_ret.set(outer.0);
+        self.metered_arg.set(outer.1);
+        result
+    }
+}
@@ -0,0 +1,114 @@
+use std::{future::Future, time::Duration};
+
+use async_io::Timer;
+use futures_lite::future::{block_on, or};
+use nusb::{transfer::RequestBuffer, Device, DeviceInfo, Interface};
+
+use crate::{
+    constants::{
+        BB_PRODUCT_ID, IQUE_VENDOR_ID, RDB_BULK_EP_IN, RDB_BULK_EP_OUT, RDB_CONF_DESCRIPTOR,
+        RDB_INTERFACE,
+    },
+    error::{permissions_error, LibBBError, Result},
+    lock::DeviceLock,
+    transport::Transport,
+};
+#[cfg(not(feature = "rusb"))]
+use crate::{BBPlayer, BBPlayerBuilder};
+
+#[derive(Debug)]
+pub struct NusbTransport {
+    device: Device,
+    interface: Interface,
+    lock: Option<DeviceLock>,
+}
+
+impl NusbTransport {
+    pub fn is_bbp(device: &DeviceInfo) -> bool {
+        device.vendor_id() == IQUE_VENDOR_ID && device.product_id() == BB_PRODUCT_ID
+    }
+
+    pub fn get_players() -> Result<Vec<DeviceInfo>> {
+        Ok(nusb::list_devices()?.filter(Self::is_bbp).collect())
+    }
+
+    pub fn open(device: &DeviceInfo) -> Result<Self> {
+        let lock = DeviceLock::acquire(&format!(
+            "{}-{}",
+            device.bus_number(),
+            device.device_address()
+        ))?;
+        let device = match device.open() {
+            Ok(d) => d,
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                return Err(permissions_error())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        device.set_configuration(RDB_CONF_DESCRIPTOR)?;
+
+        let interface = device.detach_and_claim_interface(RDB_INTERFACE)?;
+        interface.clear_halt(RDB_BULK_EP_IN)?;
+        interface.clear_halt(RDB_BULK_EP_OUT)?;
+
+        Ok(Self {
+            device,
+            interface,
+            lock: Some(lock),
+        })
+    }
+
+    // nusb transfers have no timeout of their own; dropping the future cancels the transfer
+    fn wait<F: Future>(fut: F, timeout: Duration) -> Result<F::Output> {
+        block_on(or(async { Some(fut.await) }, async {
+            Timer::after(timeout).await;
+            None
+        }))
+        .ok_or(LibBBError::Timeout)
+    }
+}
+
+impl Transport for NusbTransport {
+    fn send(&self, data: &[u8], timeout: Duration) -> Result<usize> {
+        let completion = Self::wait(
+            self.interface.bulk_out(RDB_BULK_EP_OUT, data.to_vec()),
+            timeout,
+        )?;
+        Ok(completion.into_result()?.actual_length())
+    }
+
+    fn receive(&self, length: usize, timeout: Duration) -> Result<Vec<u8>> {
+        let completion = Self::wait(
+            self.interface
+                .bulk_in(RDB_BULK_EP_IN, RequestBuffer::new(length)),
+            timeout,
+        )?;
+        Ok(completion.into_result()?)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        Ok(self.device.reset()?)
+    }
+
+    fn clear_halt(&mut self) -> Result<()> {
+        self.interface.clear_halt(RDB_BULK_EP_IN)?;
+        self.interface.clear_halt(RDB_BULK_EP_OUT)?;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.lock = None;
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "rusb"))]
+impl BBPlayer {
+    pub fn get_players() -> Result<Vec<DeviceInfo>> {
+        NusbTransport::get_players()
+    }
+
+    pub fn new(device: &DeviceInfo) -> Result<Self> {
+        BBPlayerBuilder::new().open(device)
+    }
+}
@@ -1,7 +1,17 @@
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use log::{debug, trace, warn};
+
 use crate::{
-    constants::{PACKET_SIZE, SEND_CHUNK_SIZE, TIMEOUT},
+    constants::{PACKET_SIZE, RESYNC_TIMEOUT, SEND_CHUNK_SIZE},
     error::{LibBBError, Result},
-    num_from_arr, BBPlayer,
+    events::Event,
+    num_from_arr,
+    transport::Transport,
+    BBPlayer,
 };
 
 #[repr(u8)]
@@ -16,8 +26,8 @@ pub(crate) enum TransferCommand {
     SendChunk = 0x63,
 }
 
-impl BBPlayer {
-    const READY_SIGNAL: [u8; 4] = [TransferCommand::Ready as u8, 0x00, 0x00, 0x00];
+impl<B: Transport> BBPlayer<B> {
+    pub(crate) const READY_SIGNAL: [u8; 4] = [TransferCommand::Ready as u8, 0x00, 0x00, 0x00];
 
     const PIECEMEAL_DATA_CHUNK_SIZE: usize = 3;
 
@@ -28,19 +38,75 @@ impl BBPlayer {
                 chunk,
             ]
             .concat();
-            self.bulk_transfer_send(chunk_buf, TIMEOUT)?;
+            self.bulk_transfer_send(chunk_buf, self.timeouts.block_write)?;
         }
 
         Ok(())
     }
 
     pub fn wait_ready(&self) -> Result<()> {
-        while !self.is_ready()? {}
-        Ok(())
+        self.wait_ready_within(self.ready_policy.max_wait)
+    }
+
+    // for the commands ReadyPolicy::slow_max_wait covers
+    pub(crate) fn wait_ready_slow(&self) -> Result<()> {
+        self.wait_ready_within(self.ready_policy.slow_max_wait)
+    }
+
+    fn wait_ready_within(&self, max_wait: Option<Duration>) -> Result<()> {
+        if self.ready_pending.replace(false) {
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        let mut interval = self.ready_policy.poll_interval;
+        loop {
+            match self.is_ready() {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(e) if e.is_timeout() => {}
+                Err(e) => return Err(e),
+            }
+            if let Some(max_wait) = max_wait
+                && start.elapsed() >= max_wait
+            {
+                return Err(LibBBError::ReadyTimeout(max_wait));
+            }
+            if !interval.is_zero() {
+                sleep(interval);
+                interval = (interval * 2).min(self.ready_policy.max_interval);
+            }
+        }
+    }
+
+    pub fn resync(&self) -> Result<()> {
+        loop {
+            match self.bulk_transfer_receive(PACKET_SIZE, RESYNC_TIMEOUT) {
+                Ok(buf) if buf.is_empty() => return Ok(()),
+                // keep track of a drained ready signal so the next command doesn't wait for it
+                Ok(buf) => {
+                    debug!("resync: discarded {} stale bytes", buf.len());
+                    self.ready_pending.set(buf.ends_with(&Self::READY_SIGNAL))
+                }
+                Err(e) if e.is_timeout() => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub(crate) fn recover(&mut self, reset_device: bool) -> Result<()> {
+        // a transfer that died mid-block usually leaves an endpoint halted, so
+        // everything after it fails with a pipe error until the halt is cleared
+        self.transport.clear_halt()?;
+        if reset_device {
+            self.transport.reset()?;
+        }
+        self.ready_pending.set(false);
+        self.resync()
     }
 
     fn is_ready(&self) -> Result<bool> {
-        let buf = self.bulk_transfer_receive(4, TIMEOUT)?;
+        let buf = self.bulk_transfer_receive(4, self.timeouts.ready_wait)?;
         if buf.len() != 4 {
             Err(LibBBError::TransferLength(4, buf.len()))
         } else {
@@ -78,10 +144,17 @@ impl BBPlayer {
     }
 
     pub fn send_piecemeal_data<T: AsRef<[u8]>>(&self, data: T) -> Result<usize> {
-        self.bulk_transfer_send(Self::encode_piecemeal_data(data.as_ref()), TIMEOUT)
+        self.bulk_transfer_send(
+            Self::encode_piecemeal_data(data.as_ref()),
+            self.timeouts.command,
+        )
     }
 
     pub(crate) fn send_command(&self, command: u32, arg: u32) -> Result<()> {
+        trace!("command {command:#04X}, arg {arg:#010X}");
+        if self.metered_arg.get().is_none() {
+            self.metered_arg.set(Some(arg));
+        }
         self.wait_ready()?;
         let message = [command.to_be_bytes(), arg.to_be_bytes()].concat();
         match self.send_piecemeal_data(message) {
@@ -91,15 +164,18 @@ impl BBPlayer {
     }
 
     fn send_ack(&self) -> Result<usize> {
-        self.bulk_transfer_send([TransferCommand::Ack as u8], TIMEOUT)
+        self.bulk_transfer_send([TransferCommand::Ack as u8], self.timeouts.command)
     }
 
-    fn receive_data_length(&self) -> Result<usize> {
+    fn receive_data_length(&self, timeout: Duration) -> Result<usize> {
         let mut data;
         loop {
-            data = self.bulk_transfer_receive(4, TIMEOUT)?;
+            data = self.bulk_transfer_receive(4, timeout)?;
             if data == Self::READY_SIGNAL {
-                eprintln!("Received unexpected ready signal");
+                warn!("Received unexpected ready signal");
+                self.emit(Event::Warning(
+                    "Received unexpected ready signal".to_string(),
+                ));
                 continue;
             }
             if data.len() != 4 || data[0] != 0x1B {
@@ -113,31 +189,45 @@ impl BBPlayer {
         Ok((num_from_arr::<u32, _>(&data) & 0x00FFFFFF) as usize)
     }
 
-    fn receive_data(&self, expected_len: usize) -> Result<Vec<u8>> {
-        let mut buf = Vec::with_capacity(
-            expected_len + (expected_len / 3) + (3 - (expected_len % 3)) % 3 + 1,
-        );
-        let mut transferred = PACKET_SIZE;
+    fn receive_data(&self, expected_len: usize, timeout: Duration) -> Result<Vec<u8>> {
+        let mut requested = expected_len + (expected_len / 3) + (3 - (expected_len % 3)) % 3 + 1;
+        let mut buf = Vec::with_capacity(requested);
 
-        while transferred == PACKET_SIZE {
-            let mut recv =
-                self.bulk_transfer_receive(PACKET_SIZE.min(buf.capacity() - buf.len()), TIMEOUT)?;
-            transferred = recv.len();
+        // ask for the whole reply in one bulk transfer rather than a packet at a
+        // time, so the host controller can keep several packets in flight; the
+        // console ends the reply with a short packet. should it pack the data
+        // less tightly than expected, keep reading until it does
+        loop {
+            let mut recv = self.bulk_transfer_receive(requested, timeout)?;
+            let transferred = recv.len();
             buf.append(&mut recv);
+            if transferred < requested {
+                break;
+            }
+            requested = PACKET_SIZE;
         }
         self.send_ack()?;
         Self::decode_piecemeal_data(&buf, expected_len)
     }
 
     pub fn receive_reply(&self, expected_len: usize) -> Result<Vec<u8>> {
-        let data_length = self.receive_data_length()?;
+        self.receive_reply_within(expected_len, self.timeouts.command)
+    }
+
+    pub(crate) fn receive_reply_within(
+        &self,
+        expected_len: usize,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        let data_length = self.receive_data_length(timeout)?;
+        trace!("reply of {data_length} bytes");
         if data_length == 0 || data_length > expected_len {
             Err(LibBBError::InvalidReplyLength(
                 expected_len,
                 data_length,
             ))
         } else {
-            self.receive_data(data_length)
+            self.receive_data(data_length, timeout)
         }
     }
 }
@@ -0,0 +1,73 @@
+use crate::{
+    error::{LibBBError, Result},
+    num_from_arr,
+    replies::FromReply,
+    transport::Transport,
+    BBPlayer,
+};
+
+// none of this checks what the console will make of it; a wrong command or a
+// reply left unread will desync the session (see BBPlayer::resync)
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandReply(pub [u8; 8]);
+
+impl CommandReply {
+    pub fn word(&self) -> u32 {
+        num_from_arr(&self.0[..4])
+    }
+
+    // most commands return a status here, negative on failure
+    pub fn value(&self) -> i32 {
+        num_from_arr(&self.0[4..8])
+    }
+}
+
+impl FromReply for CommandReply {
+    const LEN: usize = 8;
+
+    fn from_reply(reply: &[u8]) -> Self {
+        Self(reply.try_into().unwrap())
+    }
+
+    fn status(&self) -> Option<i32> {
+        Some(self.value())
+    }
+}
+
+impl<B: Transport> BBPlayer<B> {
+    #[allow(non_snake_case)]
+    pub fn SendRawCommand(&self, command: u32, arg: u32) -> Result<CommandReply> {
+        self.SendRawCommandNoReply(command, arg)?;
+        let reply = self.receive_reply(CommandReply::LEN)?;
+        if reply.len() != CommandReply::LEN {
+            return Err(LibBBError::TransferLength(CommandReply::LEN, reply.len()));
+        }
+        Ok(CommandReply::from_reply(&reply))
+    }
+
+    #[allow(non_snake_case)]
+    pub fn SendRawCommandNoReply(&self, command: u32, arg: u32) -> Result<()> {
+        self.send_command(command, arg)
+    }
+
+    // waits for the ready signal, then sends data in the piecemeal encoding
+    // used for command parameters
+    #[allow(non_snake_case)]
+    pub fn SendRawData<T: AsRef<[u8]>>(&self, data: T) -> Result<()> {
+        self.wait_ready()?;
+        self.send_piecemeal_data(data)?;
+        Ok(())
+    }
+
+    // sends data in the chunked encoding used for block contents
+    #[allow(non_snake_case)]
+    pub fn SendRawChunkedData<T: AsRef<[u8]>>(&self, data: T) -> Result<()> {
+        self.send_chunked_data(data)
+    }
+
+    #[allow(non_snake_case)]
+    pub fn ReceiveRawReply(&self, max_len: usize) -> Result<Vec<u8>> {
+        self.receive_reply(max_len)
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{
+    constants::{REMOTE_LATENCY_ALLOWANCE, REMOTE_MAX_TRANSFER},
+    error::{LibBBError, Result},
+    transport::Transport,
+};
+
+// request:  op (u8), timeout in ms (u32 BE), length (u32 BE), payload (send only)
+// response: status (u8), length (u32 BE), payload
+//
+// for a send the response length is the number of bytes written, for a receive
+// it's the length of the payload that follows, and for an error it's the length
+// of the UTF-8 message that follows
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RemoteOp {
+    Send = 0,
+    Receive = 1,
+    Reset = 2,
+    Close = 3,
+    ClearHalt = 4,
+}
+
+impl TryFrom<u8> for RemoteOp {
+    type Error = LibBBError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Send),
+            1 => Ok(Self::Receive),
+            2 => Ok(Self::Reset),
+            3 => Ok(Self::Close),
+            4 => Ok(Self::ClearHalt),
+            _ => Err(LibBBError::RemoteProtocol(value)),
+        }
+    }
+}
+
+#[repr(u8)]
+enum RemoteStatus {
+    Ok = 0,
+    Timeout = 1,
+    Error = 2,
+}
+
+#[derive(Debug)]
+pub struct RemoteTransport {
+    stream: TcpStream,
+}
+
+impl RemoteTransport {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self { stream })
+    }
+
+    fn request(
+        &self,
+        op: RemoteOp,
+        timeout: Duration,
+        length: usize,
+        payload: &[u8],
+    ) -> Result<(usize, Vec<u8>)> {
+        let mut stream = &self.stream;
+
+        let header = [
+            &[op as u8][..],
+            &(timeout.as_millis() as u32).to_be_bytes(),
+            &(length as u32).to_be_bytes(),
+        ]
+        .concat();
+        stream.write_all(&[&header, payload].concat())?;
+
+        stream.set_read_timeout(Some(timeout + REMOTE_LATENCY_ALLOWANCE))?;
+        let mut status = [0u8; 5];
+        stream.read_exact(&mut status)?;
+        let length = u32::from_be_bytes(status[1..5].try_into().unwrap()) as usize;
+
+        match status[0] {
+            s if s == RemoteStatus::Ok as u8 => {
+                let mut buf = vec![0u8; if op == RemoteOp::Receive { length } else { 0 }];
+                stream.read_exact(&mut buf)?;
+                Ok((length, buf))
+            }
+            s if s == RemoteStatus::Timeout as u8 => Err(LibBBError::Timeout),
+            s if s == RemoteStatus::Error as u8 => {
+                let mut buf = vec![0u8; length];
+                stream.read_exact(&mut buf)?;
+                Err(LibBBError::Remote(
+                    String::from_utf8_lossy(&buf).into_owned(),
+                ))
+            }
+            s => Err(LibBBError::RemoteProtocol(s)),
+        }
+    }
+}
+
+impl Transport for RemoteTransport {
+    fn send(&self, data: &[u8], timeout: Duration) -> Result<usize> {
+        Ok(self.request(RemoteOp::Send, timeout, data.len(), data)?.0)
+    }
+
+    fn receive(&self, length: usize, timeout: Duration) -> Result<Vec<u8>> {
+        Ok(self.request(RemoteOp::Receive, timeout, length, &[])?.1)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.request(RemoteOp::Reset, Duration::ZERO, 0, &[])?;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.request(RemoteOp::Close, Duration::ZERO, 0, &[])?;
+        Ok(())
+    }
+
+    fn clear_halt(&mut self) -> Result<()> {
+        self.request(RemoteOp::ClearHalt, Duration::ZERO, 0, &[])?;
+        Ok(())
+    }
+}
+
+// daemon side: forward requests from a single client to a locally attached transport
+// until the client disconnects or closes the session
+pub fn serve_remote<B: Transport>(transport: &mut B, mut stream: TcpStream) -> Result<()> {
+    stream.set_nodelay(true)?;
+
+    loop {
+        let mut header = [0u8; 9];
+        match stream.read_exact(&mut header) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        let op = RemoteOp::try_from(header[0])?;
+        let timeout =
+            Duration::from_millis(u32::from_be_bytes(header[1..5].try_into().unwrap()) as u64);
+        let length = u32::from_be_bytes(header[5..9].try_into().unwrap()) as usize;
+
+        // the length comes from the client, so don't trust it with an allocation.
+        // an oversized send's payload can't be skipped, so that ends the session
+        let too_long =
+            matches!(op, RemoteOp::Send | RemoteOp::Receive) && length > REMOTE_MAX_TRANSFER;
+        let result = match op {
+            _ if too_long => Err(LibBBError::Remote(format!(
+                "transfer of {length} bytes is over the {REMOTE_MAX_TRANSFER} byte limit"
+            ))),
+            RemoteOp::Send => {
+                let mut data = vec![0u8; length];
+                stream.read_exact(&mut data)?;
+                transport.send(&data, timeout).map(|sent| (sent, vec![]))
+            }
+            RemoteOp::Receive => transport
+                .receive(length, timeout)
+                .map(|buf| (buf.len(), buf)),
+            RemoteOp::Reset => transport.reset().map(|_| (0, vec![])),
+            RemoteOp::Close => transport.close().map(|_| (0, vec![])),
+            RemoteOp::ClearHalt => transport.clear_halt().map(|_| (0, vec![])),
+        };
+
+        let reply = match result {
+            Ok((length, payload)) => [
+                &[RemoteStatus::Ok as u8][..],
+                &(length as u32).to_be_bytes(),
+                &payload,
+            ]
+            .concat(),
+            Err(e) if e.is_timeout() => [RemoteStatus::Timeout as u8, 0, 0, 0, 0].to_vec(),
+            Err(e) => {
+                let message = e.to_string();
+                [
+                    &[RemoteStatus::Error as u8][..],
+                    &(message.len() as u32).to_be_bytes(),
+                    message.as_bytes(),
+                ]
+                .concat()
+            }
+        };
+        stream.write_all(&reply)?;
+
+        if op == RemoteOp::Close || (too_long && op == RemoteOp::Send) {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use crate::{
+    commands::{Command, Signature},
+    constants::SIGNATURE_SIZE,
+    error::{LibBBError, Result},
+    num_from_arr,
+    transport::Transport,
+    BBPlayer,
+};
+
+// a reply the console sends in one piece; from_reply is only ever handed
+// exactly LEN bytes, so it can slice without checking
+pub(crate) trait FromReply: Sized {
+    const LEN: usize;
+
+    fn from_reply(reply: &[u8]) -> Self;
+
+    // the status word, for replies that carry one (see CommandMetrics::result)
+    fn status(&self) -> Option<i32> {
+        None
+    }
+}
+
+// most commands reply with two words: the first is unused, the second is a
+// status that's negative on failure
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StatusReply(pub(crate) i32);
+
+impl FromReply for StatusReply {
+    const LEN: usize = 8;
+
+    fn from_reply(reply: &[u8]) -> Self {
+        Self(num_from_arr(&reply[4..8]))
+    }
+
+    fn status(&self) -> Option<i32> {
+        Some(self.0)
+    }
+}
+
+impl StatusReply {
+    pub(crate) fn check<F: FnOnce(i32) -> LibBBError>(self, err: F) -> Result<u32> {
+        if self.0 < 0 {
+            Err(err(self.0))
+        } else {
+            Ok(self.0 as u32)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct NumBlocksReply(pub(crate) u32);
+
+impl FromReply for NumBlocksReply {
+    const LEN: usize = 8;
+
+    fn from_reply(reply: &[u8]) -> Self {
+        Self(num_from_arr(&reply[4..8]))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SeqNoReply(pub(crate) u32);
+
+impl FromReply for SeqNoReply {
+    const LEN: usize = 8;
+
+    fn from_reply(reply: &[u8]) -> Self {
+        Self(num_from_arr(&reply[4..8]))
+    }
+}
+
+// FileChksum replies 0 when the file's checksum matches
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChecksumReply(pub(crate) i32);
+
+impl FromReply for ChecksumReply {
+    const LEN: usize = 8;
+
+    fn from_reply(reply: &[u8]) -> Self {
+        Self(num_from_arr(&reply[4..8]))
+    }
+
+    fn status(&self) -> Option<i32> {
+        Some(self.0)
+    }
+}
+
+impl ChecksumReply {
+    pub(crate) fn matches(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl FromReply for Signature {
+    const LEN: usize = SIGNATURE_SIZE;
+
+    fn from_reply(reply: &[u8]) -> Self {
+        Self(reply.try_into().unwrap())
+    }
+}
+
+impl<B: Transport> BBPlayer<B> {
+    pub(crate) fn receive_typed<T: FromReply>(&self, command: Command) -> Result<T> {
+        self.receive_typed_within(command, self.timeouts.command)
+    }
+
+    pub(crate) fn receive_typed_within<T: FromReply>(
+        &self,
+        command: Command,
+        timeout: Duration,
+    ) -> Result<T> {
+        let reply = self.receive_reply_within(T::LEN, timeout)?;
+        if reply.len() != T::LEN {
+            return Err(LibBBError::MalformedReply(command, T::LEN, reply.len()));
+        }
+        let reply = T::from_reply(&reply);
+        if let Some(status) = reply.status() {
+            self.last_ret.set(Some(status));
+        }
+        Ok(reply)
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::{commands::LedState, events::Event, transport::Transport, BBPlayer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BulkProgress {
+    pub operation: &'static str,
+    pub done: u32,
+    pub total: u32,
+    pub started: Instant,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub transfers_in: u64,
+    pub transfers_out: u64,
+    pub retries: u64,
+    pub blocks_read: u64,
+    pub block_read_time: Duration,
+    pub bulk: Option<BulkProgress>,
+}
+
+impl TransferStats {
+    pub fn average_block_read_time(&self) -> Option<Duration> {
+        (self.blocks_read > 0).then(|| self.block_read_time / self.blocks_read as u32)
+    }
+
+    // extrapolated from how long the blocks done so far in the current bulk
+    // operation (dump or write) have taken
+    pub fn estimated_remaining(&self) -> Option<Duration> {
+        let bulk = self.bulk?;
+        if bulk.done == 0 {
+            return None;
+        }
+        Some(bulk.started.elapsed() / bulk.done * (bulk.total - bulk.done))
+    }
+}
+
+impl<B: Transport> BBPlayer<B> {
+    pub fn transfer_stats(&self) -> TransferStats {
+        self.stats.get()
+    }
+
+    pub fn reset_transfer_stats(&self) {
+        self.stats.set(TransferStats::default());
+    }
+
+    pub(crate) fn update_stats<F: FnOnce(&mut TransferStats)>(&self, f: F) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    pub(crate) fn start_bulk(&self, operation: &'static str, total: u32) -> BulkGuard<'_, B> {
+        self.update_stats(|s| {
+            s.bulk = Some(BulkProgress {
+                operation,
+                done: 0,
+                total,
+                started: Instant::now(),
+            })
+        });
+        self.emit(Event::OperationStarted { operation, total });
+        if let Some(feedback) = self.led_feedback {
+            self.show_led(feedback.busy);
+        }
+        BulkGuard {
+            player: self,
+            operation,
+            finished: false,
+        }
+    }
+}
+
+impl<B: Transport> BBPlayer<B> {
+    // the LED is only a nicety, so failing to set it mustn't fail the operation
+    fn show_led(&self, state: LedState) {
+        if let Err(e) = self.set_led(state) {
+            warn!("failed to set LED: {e}");
+        }
+    }
+}
+
+// tracks a bulk operation's progress; dropping it without calling finish (an
+// early return through ?, or a cancellation) reports the operation as failed
+pub(crate) struct BulkGuard<'a, B: Transport> {
+    player: &'a BBPlayer<B>,
+    operation: &'static str,
+    finished: bool,
+}
+
+impl<B: Transport> BulkGuard<'_, B> {
+    pub(crate) fn advance(&self) {
+        let mut progress = None;
+        self.player.update_stats(|s| {
+            if let Some(bulk) = &mut s.bulk {
+                bulk.done += 1;
+                progress = Some((bulk.done, bulk.total));
+            }
+        });
+        if let Some((done, total)) = progress {
+            self.player.emit(Event::OperationProgress {
+                operation: self.operation,
+                done,
+                total,
+            });
+        }
+    }
+
+    pub(crate) fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl<B: Transport> Drop for BulkGuard<'_, B> {
+    fn drop(&mut self) {
+        self.player.update_stats(|s| s.bulk = None);
+        self.player.emit(Event::OperationFinished {
+            operation: self.operation,
+            success: self.finished,
+        });
+        if let Some(feedback) = self.player.led_feedback {
+            if self.finished {
+                self.player.show_led(feedback.done);
+            } else {
+                // whatever went wrong may have left a reply half read
+                let _ = self.player.resync();
+                self.player.show_led(feedback.error);
+            }
+        }
+    }
+}
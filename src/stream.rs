@@ -0,0 +1,166 @@
+use std::io::{self, Read, Write};
+
+use log::warn;
+
+use crate::{
+    constants::{BLANK_SPARE, BLOCK_SIZE},
+    error::{LibBBError, Result},
+    filename::FatFilename,
+    transport::Transport,
+    BBPlayer,
+};
+
+// reads a console file a block at a time, as it's asked for
+#[derive(Debug)]
+pub struct FileReader<'a, B: Transport> {
+    player: &'a BBPlayer<B>,
+    blocks: Vec<u16>,
+    size: usize,
+    pos: usize,
+    current: Option<(usize, Vec<u8>)>,
+}
+
+// writes a console file a block at a time into free blocks; nothing on the
+// console refers to them until finish links them up and commits the FS, so a
+// writer dropped without finishing leaves the existing file as it was. the
+// blocks are picked from the FS as it was at open_write, so if anything else
+// commits an FS in the meantime finish gives up rather than link them
+#[derive(Debug)]
+pub struct FileWriter<'a, B: Transport> {
+    player: &'a mut BBPlayer<B>,
+    filename: String,
+    pending: Vec<u8>,
+    blocks: Vec<u16>,
+    chksum: u32,
+    finished: bool,
+}
+
+impl<B: Transport> BBPlayer<B> {
+    pub fn open_read<T: AsRef<str>>(&self, filename: T) -> Result<FileReader<'_, B>> {
+        if !self.is_initialised {
+            return Err(LibBBError::NoConsole);
+        }
+        let filename = FatFilename::new(filename)?;
+        let filename = filename.as_str();
+        let Some(size) = self.find_file(filename)?.map(|f| f.size()) else {
+            return Err(LibBBError::FileNotFound(filename.to_string()));
+        };
+        let blocks = self.list_file_blocks(filename)?.unwrap_or_default();
+        Ok(FileReader {
+            player: self,
+            blocks,
+            size: size as usize,
+            pos: 0,
+            current: None,
+        })
+    }
+
+    pub fn open_write<T: AsRef<str>>(&mut self, filename: T) -> Result<FileWriter<'_, B>> {
+        if !self.is_initialised {
+            return Err(LibBBError::NoConsole);
+        }
+        let filename = FatFilename::new(filename)?;
+        self.refresh_if_stale()?;
+        Ok(FileWriter {
+            player: self,
+            filename: filename.to_string(),
+            pending: Vec::with_capacity(BLOCK_SIZE),
+            blocks: vec![],
+            chksum: 0,
+            finished: false,
+        })
+    }
+}
+
+impl<B: Transport> FileReader<'_, B> {
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+}
+
+impl<B: Transport> Read for FileReader<'_, B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let index = self.pos / BLOCK_SIZE;
+        if self.pos >= self.size || index >= self.blocks.len() {
+            return Ok(0);
+        }
+
+        if !matches!(&self.current, Some((i, _)) if *i == index) {
+            let (block, _) = self
+                .player
+                .read_block_spare(self.blocks[index].into())
+                .map_err(io::Error::other)?;
+            self.current = Some((index, block));
+        }
+        let Some((_, block)) = &self.current else {
+            unreachable!()
+        };
+
+        let offset = self.pos % BLOCK_SIZE;
+        let len = buf.len().min(BLOCK_SIZE - offset).min(self.size - self.pos);
+        buf[..len].copy_from_slice(&block[offset..offset + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl<B: Transport> FileWriter<'_, B> {
+    // writes out whatever's left (padded to a whole block, as WriteFile does)
+    // and replaces any existing file of the same name
+    pub fn finish(mut self) -> Result<()> {
+        self.finished = true;
+        if !self.pending.is_empty() || self.blocks.is_empty() {
+            let mut block = std::mem::take(&mut self.pending);
+            block.resize(BLOCK_SIZE, 0);
+            self.write_block(&block)?;
+        }
+
+        if self.player.fs_is_stale()? {
+            return Err(LibBBError::FSChanged(self.filename.clone()));
+        }
+        let (filename, blocks, chksum) = (&self.filename, &self.blocks, self.chksum);
+        self.player
+            .with_fs_rollback(|player| player.commit_streamed_file(filename, blocks, chksum))
+    }
+
+    fn write_block(&mut self, block: &[u8]) -> Result<()> {
+        let next = self
+            .player
+            .next_free_block_after(self.blocks.last().copied())?;
+        self.player
+            .write_block_spare(block, &BLANK_SPARE, next.into(), false)?;
+        self.blocks.push(next);
+        Ok(())
+    }
+}
+
+impl<B: Transport> Write for FileWriter<'_, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.chksum = buf
+            .iter()
+            .fold(self.chksum, |a, &e| a.wrapping_add(e as u32));
+        self.pending.extend_from_slice(buf);
+        while self.pending.len() >= BLOCK_SIZE {
+            let block: Vec<u8> = self.pending.drain(..BLOCK_SIZE).collect();
+            self.write_block(&block).map_err(io::Error::other)?;
+        }
+        Ok(buf.len())
+    }
+
+    // only whole blocks can be written, so a partial one waits for finish
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<B: Transport> Drop for FileWriter<'_, B> {
+    fn drop(&mut self) {
+        if !self.finished {
+            warn!("{} was never finished; discarding it", self.filename);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::Instant,
+};
+
+use log::warn;
+
+use crate::{
+    error::Result,
+    metrics::{CommandMetrics, MetricsSink},
+    transport::Transport,
+    BBPlayer,
+};
+
+// records every command as one JSON object per line:
+// {"us":<microseconds since the transcript started>,"command":"<name>","code":<command word>,
+//  "arg":<argument or null>,"result":<status or null>,"retries":<n>,"elapsed_us":<n>,
+//  "bytes_in":<n>,"bytes_out":<n>,"success":<bool>}
+// unlike CaptureTransport this doesn't hold the data itself, so it's small enough
+// to attach to a bug report
+#[derive(Debug)]
+pub struct Transcript<W: Write + Send = BufWriter<File>> {
+    writer: Mutex<W>,
+    start: Instant,
+}
+
+impl Transcript {
+    pub fn to_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Send> Transcript<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl<W: Write + Send> MetricsSink for Transcript<W> {
+    fn record(&self, metrics: &CommandMetrics) {
+        let mut line = format!(
+            "{{\"us\":{},\"command\":\"{:?}\",\"code\":{},",
+            self.start.elapsed().as_micros(),
+            metrics.command,
+            metrics.command as u32
+        );
+        let _ = match metrics.arg {
+            Some(arg) => write!(line, "\"arg\":{arg},"),
+            None => write!(line, "\"arg\":null,"),
+        };
+        let _ = match metrics.result {
+            Some(result) => write!(line, "\"result\":{result},"),
+            None => write!(line, "\"result\":null,"),
+        };
+        let _ = write!(
+            line,
+            "\"retries\":{},\"elapsed_us\":{},\"bytes_in\":{},\"bytes_out\":{},\"success\":{}}}",
+            metrics.retries,
+            metrics.elapsed.as_micros(),
+            metrics.bytes_in,
+            metrics.bytes_out,
+            metrics.success
+        );
+
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        // flushed every line, so a crash doesn't lose the part that matters
+        if let Err(e) = writeln!(writer, "{line}").and_then(|_| writer.flush()) {
+            warn!("failed to write transcript: {e}");
+        }
+    }
+}
+
+impl<B: Transport> BBPlayer<B> {
+    // takes the place of any metrics sink already set
+    pub fn record_transcript<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.set_metrics_sink(Transcript::to_file(path)?);
+        Ok(())
+    }
+}
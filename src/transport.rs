@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use crate::{error::Result, events::Event, BBPlayer};
+
+pub trait Transport {
+    fn send(&self, data: &[u8], timeout: Duration) -> Result<usize>;
+
+    fn receive(&self, length: usize, timeout: Duration) -> Result<Vec<u8>>;
+
+    fn reset(&mut self) -> Result<()>;
+
+    fn clear_halt(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<B: Transport> BBPlayer<B> {
+    fn detect_detach<T>(&self, result: Result<T>) -> Result<T> {
+        if let Err(e) = &result
+            && e.is_disconnect()
+        {
+            self.emit(Event::DeviceDetached);
+        }
+        result
+    }
+
+    pub fn close_connection(&mut self) -> Result<()> {
+        self.transport.close()
+    }
+
+    pub fn bulk_transfer_send<T: AsRef<[u8]>>(&self, data: T, timeout: Duration) -> Result<usize> {
+        let sent = self.detect_detach(self.transport.send(data.as_ref(), timeout))?;
+        self.update_stats(|s| {
+            s.bytes_out += sent as u64;
+            s.transfers_out += 1;
+        });
+        Ok(sent)
+    }
+
+    pub fn bulk_transfer_receive(&self, length: usize, timeout: Duration) -> Result<Vec<u8>> {
+        let buf = self.detect_detach(self.transport.receive(length, timeout))?;
+        self.update_stats(|s| {
+            s.bytes_in += buf.len() as u64;
+            s.transfers_in += 1;
+        });
+        Ok(buf)
+    }
+}
@@ -1,76 +1,257 @@
-use std::time::Duration;
+use std::{fmt, time::Duration};
 
-use rusb::{Device, DeviceHandle, GlobalContext};
+use log::{debug, warn};
+use rusb::{Device, DeviceHandle, Direction, GlobalContext, Speed, TransferType, UsbContext};
 
 use crate::{
     constants::{
         BB_PRODUCT_ID, IQUE_VENDOR_ID, RDB_BULK_EP_IN, RDB_BULK_EP_OUT, RDB_CONF_DESCRIPTOR,
-        RDB_INTERFACE,
+        RDB_INTERFACE, VENDOR_SPECIFIC_CLASS,
     },
-    error::{wrap_libusb_error, LibBBError, Result},
-    BBPlayer,
+    error::{permissions_error, wrap_libusb_error, LibBBError, Result},
+    events::Event,
+    lock::DeviceLock,
+    transport::Transport,
+    BBPlayer, BBPlayerBuilder,
 };
 
-impl BBPlayer {
-    pub fn is_bbp(device: &Device<GlobalContext>) -> Result<bool> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Endpoints {
+    pub config: u8,
+    pub interface: u8,
+    pub bulk_in: u8,
+    pub bulk_out: u8,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            config: RDB_CONF_DESCRIPTOR,
+            interface: RDB_INTERFACE,
+            bulk_in: RDB_BULK_EP_IN,
+            bulk_out: RDB_BULK_EP_OUT,
+        }
+    }
+}
+
+impl Endpoints {
+    // look for a vendor-specific interface with a bulk endpoint in each direction,
+    // preferring the usual RDB configuration; anything else gets the standard layout
+    pub fn discover<C: UsbContext>(device: &Device<C>) -> Self {
+        let Ok(desc) = device.device_descriptor() else {
+            return Self::default();
+        };
+
+        let mut found = None;
+        for index in 0..desc.num_configurations() {
+            let Ok(config) = device.config_descriptor(index) else {
+                continue;
+            };
+            for interface in config.interfaces() {
+                for alt in interface.descriptors() {
+                    if alt.class_code() != VENDOR_SPECIFIC_CLASS {
+                        continue;
+                    }
+                    let bulk = |dir| {
+                        alt.endpoint_descriptors()
+                            .find(|ep| {
+                                ep.transfer_type() == TransferType::Bulk && ep.direction() == dir
+                            })
+                            .map(|ep| ep.address())
+                    };
+                    if let (Some(bulk_in), Some(bulk_out)) =
+                        (bulk(Direction::In), bulk(Direction::Out))
+                    {
+                        let endpoints = Self {
+                            config: config.number(),
+                            interface: alt.interface_number(),
+                            bulk_in,
+                            bulk_out,
+                        };
+                        if endpoints.config == RDB_CONF_DESCRIPTOR {
+                            return endpoints;
+                        }
+                        found.get_or_insert(endpoints);
+                    }
+                }
+            }
+        }
+
+        found.unwrap_or_default()
+    }
+}
+
+pub struct UsbTransport<C: UsbContext = GlobalContext> {
+    pub(crate) handle: DeviceHandle<C>,
+    endpoints: Endpoints,
+    lock: Option<DeviceLock>,
+}
+
+impl<C: UsbContext> fmt::Debug for UsbTransport<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UsbTransport")
+            .field("handle", &self.handle)
+            .field("endpoints", &self.endpoints)
+            .field("lock", &self.lock)
+            .finish()
+    }
+}
+
+impl<C: UsbContext> UsbTransport<C> {
+    pub fn is_bbp(device: &Device<C>) -> Result<bool> {
         let desc = wrap_libusb_error(device.device_descriptor())?;
 
         Ok(desc.vendor_id() == IQUE_VENDOR_ID && desc.product_id() == BB_PRODUCT_ID)
     }
 
-    fn is_correct_descriptor(device: &Device<GlobalContext>) -> Result<bool> {
+    fn is_correct_descriptor(device: &Device<C>, config: u8) -> Result<bool> {
         match device.active_config_descriptor() {
-            Ok(d) => Ok(d.number() == RDB_CONF_DESCRIPTOR),
+            Ok(d) => Ok(d.number() == config),
             Err(e) => Err(e.into()),
         }
     }
 
-    pub fn open_device(device: &Device<GlobalContext>) -> Result<DeviceHandle<GlobalContext>> {
-        let mut handle = device.open()?;
+    pub fn endpoints(&self) -> Endpoints {
+        self.endpoints
+    }
 
-        #[cfg(not(target_os = "windows"))]
-        if rusb::supports_detach_kernel_driver() && handle.kernel_driver_active(RDB_INTERFACE)? {
-            handle.detach_kernel_driver(RDB_INTERFACE)?;
+    pub fn open(device: &Device<C>) -> Result<Self> {
+        Self::open_with(device, true)
+    }
+
+    // which driver (if any) sysfs says is bound to the interface; "usbfs" means
+    // another userspace program has claimed it
+    fn bound_driver(device: &Device<C>, endpoints: &Endpoints) -> Option<String> {
+        #[cfg(target_os = "linux")]
+        {
+            let path = format!(
+                "/sys/bus/usb/devices/{}-{}:{}.{}/driver",
+                device.bus_number(),
+                Self::port_path(device),
+                endpoints.config,
+                endpoints.interface
+            );
+            std::fs::read_link(path)
+                .ok()?
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (device, endpoints);
+            None
         }
+    }
 
-        handle.set_active_configuration(RDB_CONF_DESCRIPTOR)?;
+    // the hardware ID plus the port the console is plugged into, as shown in
+    // Device Manager's location details
+    #[cfg(target_os = "windows")]
+    fn instance_path(device: &Device<C>) -> String {
+        format!(
+            "USB\\VID_{IQUE_VENDOR_ID:04X}&PID_{BB_PRODUCT_ID:04X}, bus {} port {}",
+            device.bus_number(),
+            Self::port_path(device)
+        )
+    }
 
-        if !Self::is_correct_descriptor(device)? {
-            return Err(LibBBError::IncorrectDescriptor);
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    fn port_path(device: &Device<C>) -> String {
+        device
+            .port_numbers()
+            .unwrap_or_default()
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    // without force_detach, a bound kernel driver is reported rather than
+    // detached
+    pub fn open_with(device: &Device<C>, force_detach: bool) -> Result<Self> {
+        let lock = DeviceLock::acquire(&format!("{}-{}", device.bus_number(), device.address()))?;
+        let endpoints = Endpoints::discover(device);
+        let mut handle = match device.open() {
+            Ok(h) => h,
+            Err(rusb::Error::Access) => return Err(permissions_error()),
+            // libusb can only open devices bound to WinUSB (or libusbK/libusb0)
+            #[cfg(target_os = "windows")]
+            Err(rusb::Error::NotSupported | rusb::Error::NotFound) => {
+                return Err(LibBBError::DriverMissing(Self::instance_path(device)))
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        #[cfg(target_os = "windows")]
+        let _ = force_detach;
+        #[cfg(not(target_os = "windows"))]
+        if rusb::supports_detach_kernel_driver()
+            && let Ok(true) = handle.kernel_driver_active(endpoints.interface)
+        {
+            let driver = Self::bound_driver(device, &endpoints);
+            if !force_detach {
+                return Err(LibBBError::KernelDriverBound(endpoints.interface, driver));
+            }
+            debug!(
+                "detaching kernel driver {} from interface {}",
+                driver.as_deref().unwrap_or("(unknown)"),
+                endpoints.interface
+            );
+            match handle.detach_kernel_driver(endpoints.interface) {
+                Ok(_) | Err(rusb::Error::NotSupported) => {}
+                Err(rusb::Error::Busy | rusb::Error::Access) => {
+                    return Err(LibBBError::KernelDriverBound(endpoints.interface, driver))
+                }
+                Err(e) => return Err(e.into()),
+            }
         }
 
-        handle.claim_interface(RDB_INTERFACE)?;
-        handle.clear_halt(RDB_BULK_EP_IN)?;
-        handle.clear_halt(RDB_BULK_EP_OUT)?;
+        handle.set_active_configuration(endpoints.config)?;
 
-        if !Self::is_correct_descriptor(device)? {
+        if !Self::is_correct_descriptor(device, endpoints.config)? {
             return Err(LibBBError::IncorrectDescriptor);
         }
 
-        Ok(handle)
-    }
+        match handle.claim_interface(endpoints.interface) {
+            Ok(_) => {}
+            Err(rusb::Error::Busy) => {
+                return Err(LibBBError::InterfaceBusy(
+                    endpoints.interface,
+                    Self::bound_driver(device, &endpoints),
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        }
+        handle.clear_halt(endpoints.bulk_in)?;
+        handle.clear_halt(endpoints.bulk_out)?;
 
-    pub fn close_connection(&mut self) -> Result<()> {
-        self.handle.release_interface(RDB_INTERFACE)?;
-        #[cfg(not(target_os = "windows"))]
-        if rusb::supports_detach_kernel_driver() {
-            self.handle.attach_kernel_driver(RDB_INTERFACE)?;
+        if !Self::is_correct_descriptor(device, endpoints.config)? {
+            return Err(LibBBError::IncorrectDescriptor);
         }
-        Ok(())
+
+        Ok(Self {
+            handle,
+            endpoints,
+            lock: Some(lock),
+        })
     }
+}
 
-    pub fn bulk_transfer_send<T: AsRef<[u8]>>(&self, data: T, timeout: Duration) -> Result<usize> {
-        //println!("send {:x?}", data.as_ref());
+impl<C: UsbContext> Transport for UsbTransport<C> {
+    fn send(&self, data: &[u8], timeout: Duration) -> Result<usize> {
+        //println!("send {:x?}", data);
         wrap_libusb_error(
             self.handle
-                .write_bulk(RDB_BULK_EP_OUT, data.as_ref(), timeout),
+                .write_bulk(self.endpoints.bulk_out, data, timeout),
         )
     }
 
-    pub fn bulk_transfer_receive(&self, length: usize, timeout: Duration) -> Result<Vec<u8>> {
+    fn receive(&self, length: usize, timeout: Duration) -> Result<Vec<u8>> {
         let mut buf = vec![0; length];
         //println!("expc {length:x}");
-        match self.handle.read_bulk(RDB_BULK_EP_IN, &mut buf, timeout) {
+        match self
+            .handle
+            .read_bulk(self.endpoints.bulk_in, &mut buf, timeout)
+        {
             Ok(n) => {
                 //println!("recv {:x?}", &buf[..n]);
                 Ok(buf[..n].to_vec())
@@ -78,4 +259,209 @@ impl BBPlayer {
             Err(e) => Err(e.into()),
         }
     }
+
+    fn reset(&mut self) -> Result<()> {
+        wrap_libusb_error(self.handle.reset())
+    }
+
+    fn clear_halt(&mut self) -> Result<()> {
+        self.handle.clear_halt(self.endpoints.bulk_in)?;
+        self.handle.clear_halt(self.endpoints.bulk_out)?;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.handle.release_interface(self.endpoints.interface)?;
+        #[cfg(not(target_os = "windows"))]
+        if rusb::supports_detach_kernel_driver() {
+            self.handle.attach_kernel_driver(self.endpoints.interface)?;
+        }
+        self.lock = None;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerInfo {
+    pub bus: u8,
+    pub address: u8,
+    pub port_numbers: Vec<u8>,
+    pub speed: Speed,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+impl PlayerInfo {
+    fn read<C: UsbContext>(device: &Device<C>, handle: Option<&DeviceHandle<C>>) -> Result<Self> {
+        let desc = wrap_libusb_error(device.device_descriptor())?;
+
+        // descriptor strings need an open handle; if the device can't be opened
+        // (e.g. it's claimed elsewhere) they're just left out
+        let opened;
+        let handle = match handle {
+            Some(h) => Some(h),
+            None => {
+                opened = device.open().ok();
+                opened.as_ref()
+            }
+        };
+        let (manufacturer, product, serial_number) = match handle {
+            Some(h) => (
+                h.read_manufacturer_string_ascii(&desc).ok(),
+                h.read_product_string_ascii(&desc).ok(),
+                h.read_serial_number_string_ascii(&desc).ok(),
+            ),
+            None => (None, None, None),
+        };
+
+        Ok(Self {
+            bus: device.bus_number(),
+            address: device.address(),
+            port_numbers: device.port_numbers().unwrap_or_default(),
+            speed: device.speed(),
+            vendor_id: desc.vendor_id(),
+            product_id: desc.product_id(),
+            manufacturer,
+            product,
+            serial_number,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    pub bus: u8,
+    pub address: u8,
+    pub bbid: u32,
+}
+
+impl BBPlayer {
+    pub fn get_players() -> Result<Vec<Device<GlobalContext>>> {
+        BBPlayer::get_players_in(&GlobalContext::default())
+    }
+
+    pub fn identify_players() -> Result<Vec<(Device<GlobalContext>, DeviceIdentity)>> {
+        BBPlayer::identify_players_in(&GlobalContext::default())
+    }
+
+    pub fn open_by_bbid(bbid: u32) -> Result<Self> {
+        BBPlayer::open_by_bbid_in(&GlobalContext::default(), bbid)
+    }
+
+    pub fn open_by_port_path(bus: u8, ports: &[u8]) -> Result<Self> {
+        BBPlayer::open_by_port_path_in(&GlobalContext::default(), bus, ports)
+    }
+}
+
+impl<C: UsbContext> BBPlayer<UsbTransport<C>> {
+    pub fn get_players_in(context: &C) -> Result<Vec<Device<C>>> {
+        let devices = context.devices()?;
+        let mut rv = vec![];
+
+        for device in devices.iter() {
+            if Self::is_bbp(&device)? {
+                rv.push(device);
+            }
+        }
+
+        Ok(rv)
+    }
+
+    pub fn new(device: &Device<C>) -> Result<Self> {
+        BBPlayerBuilder::new().open(device)
+    }
+
+    pub fn identify(device: &Device<C>) -> Result<DeviceIdentity> {
+        // only asks, so a session someone else has open on the console is left
+        // as it was
+        let player = Self::new(device)?;
+        player.get_seqno()?;
+        Ok(DeviceIdentity {
+            bus: device.bus_number(),
+            address: device.address(),
+            bbid: player.get_bbid()?,
+        })
+    }
+
+    // a console that can't be identified (say, because another process has it
+    // open) is left out rather than failing the whole scan
+    pub fn identify_players_in(context: &C) -> Result<Vec<(Device<C>, DeviceIdentity)>> {
+        let mut rv = vec![];
+        for device in Self::get_players_in(context)? {
+            match Self::identify(&device) {
+                Ok(identity) => rv.push((device, identity)),
+                Err(e) => warn!(
+                    "skipping console on bus {} address {}: {e}",
+                    device.bus_number(),
+                    device.address()
+                ),
+            }
+        }
+        Ok(rv)
+    }
+
+    // ports is the hub port chain as reported by rusb's Device::port_numbers
+    pub fn find_by_port_path_in(context: &C, bus: u8, ports: &[u8]) -> Result<Device<C>> {
+        Self::get_players_in(context)?
+            .into_iter()
+            .find(|d| d.bus_number() == bus && d.port_numbers().ok().as_deref() == Some(ports))
+            .ok_or_else(|| LibBBError::PlayerGone(bus, ports.to_vec()))
+    }
+
+    pub fn open_by_port_path_in(context: &C, bus: u8, ports: &[u8]) -> Result<Self> {
+        Self::new(&Self::find_by_port_path_in(context, bus, ports)?)
+    }
+
+    pub fn open_by_bbid_in(context: &C, bbid: u32) -> Result<Self> {
+        for (device, identity) in Self::identify_players_in(context)? {
+            if identity.bbid == bbid {
+                return Self::new(&device);
+            }
+        }
+        Err(LibBBError::NoPlayerWithBBID(bbid))
+    }
+
+    pub fn is_bbp(device: &Device<C>) -> Result<bool> {
+        UsbTransport::is_bbp(device)
+    }
+
+    pub fn open_device(device: &Device<C>) -> Result<UsbTransport<C>> {
+        UsbTransport::open(device)
+    }
+
+    // find the console on the same bus and port it was attached to before, since
+    // its address changes when it re-enumerates
+    pub fn reopen(&mut self) -> Result<()> {
+        let old = self.transport.handle.device();
+        let bus = old.bus_number();
+        let ports = old.port_numbers()?;
+
+        let device = Self::find_by_port_path_in(self.transport.handle.context(), bus, &ports)?;
+
+        // the old session's lock would otherwise block the new one if the
+        // console comes back at the same address
+        self.transport.lock = None;
+        self.transport = UsbTransport::open_with(&device, !self.keep_kernel_driver)?;
+        self.emit(Event::DeviceAttached);
+        self.ready_pending.set(false);
+        if self.is_initialised {
+            self.is_initialised = false;
+            self.Init()?;
+        }
+        Ok(())
+    }
+
+    pub fn player_info(device: &Device<C>) -> Result<PlayerInfo> {
+        PlayerInfo::read(device, None)
+    }
+
+    pub fn info(&self) -> Result<PlayerInfo> {
+        PlayerInfo::read(
+            &self.transport.handle.device(),
+            Some(&self.transport.handle),
+        )
+    }
 }
@@ -0,0 +1,101 @@
+use std::{
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    error::{LibBBError, Result},
+    transport::Transport,
+    BBPlayer, DefaultTransport,
+};
+
+type Job<B> = Box<dyn FnOnce(&mut BBPlayer<B>) + Send>;
+
+enum Message<B: Transport> {
+    Job(Job<B>),
+    Stop,
+}
+
+// runs a BBPlayer on its own thread; anything holding a WorkerHandle can queue
+// operations on it, and they run one at a time in the order they were queued
+#[derive(Debug)]
+pub struct PlayerWorker<B: Transport + Send + 'static = DefaultTransport> {
+    handle: WorkerHandle<B>,
+    thread: Option<JoinHandle<BBPlayer<B>>>,
+}
+
+#[derive(Debug)]
+pub struct WorkerHandle<B: Transport + Send + 'static = DefaultTransport> {
+    sender: Sender<Message<B>>,
+}
+
+impl<B: Transport + Send + 'static> Clone for WorkerHandle<B> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<B: Transport + Send + 'static> PlayerWorker<B> {
+    pub fn spawn(mut player: BBPlayer<B>) -> Self {
+        let (sender, receiver) = channel::<Message<B>>();
+        let thread = thread::spawn(move || {
+            while let Ok(Message::Job(job)) = receiver.recv() {
+                job(&mut player);
+            }
+            player
+        });
+        Self {
+            handle: WorkerHandle { sender },
+            thread: Some(thread),
+        }
+    }
+
+    pub fn handle(&self) -> WorkerHandle<B> {
+        self.handle.clone()
+    }
+
+    // finishes the jobs already queued, then hands the player back
+    pub fn shutdown(mut self) -> Option<BBPlayer<B>> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Option<BBPlayer<B>> {
+        let _ = self.handle.sender.send(Message::Stop);
+        self.thread.take()?.join().ok()
+    }
+}
+
+impl<B: Transport + Send + 'static> Drop for PlayerWorker<B> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl<B: Transport + Send + 'static> WorkerHandle<B> {
+    pub fn submit<T, F>(&self, f: F) -> Receiver<Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut BBPlayer<B>) -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = channel();
+        let job: Job<B> = Box::new(move |player| {
+            let _ = tx.send(f(player));
+        });
+        // if the worker's gone, tx is dropped along with the job and the
+        // receiver reports it as disconnected
+        let _ = self.sender.send(Message::Job(job));
+        rx
+    }
+
+    pub fn call<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut BBPlayer<B>) -> Result<T> + Send + 'static,
+    {
+        self.submit(f)
+            .recv()
+            .map_err(|_| LibBBError::WorkerStopped)?
+    }
+}